@@ -0,0 +1,178 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::Rng;
+
+use hnefatafl::brandubh;
+use hnefatafl::cell::CellType;
+use hnefatafl::copenhagen;
+use hnefatafl::rng::{rng_from_seed, GameRng};
+use hnefatafl::search::{SearchEngine, SearchLimits, SimpleEvaluator};
+use hnefatafl::variant::{Move, Variant};
+
+// Fixed seeds so every run benchmarks exactly the same positions and games.
+const MIDGAME_SEED: u64 = 0x7afe;
+const PLAYOUT_SEED: u64 = 0x6a3e;
+const MIDGAME_PLIES: usize = 20;
+
+fn random_move<G: Variant>(game: &G, rng: &mut GameRng) -> Option<Move> {
+    let moves = game.legal_moves();
+    if moves.is_empty() {
+        return None;
+    }
+    Some(moves[rng.random_range(0..moves.len())])
+}
+
+/// Plays random moves until the game ends, returning the number of moves played.
+fn random_playout<G: Variant>(game: &mut G, rng: &mut GameRng) -> u32 {
+    while game.outcome().is_none() {
+        match random_move(game, rng) {
+            Some(mv) => game.apply_move(mv).expect("legal move rejected"),
+            None => break,
+        }
+    }
+    game.move_count()
+}
+
+/// A position reached after `MIDGAME_PLIES` random moves that is still undecided.
+fn midgame<G: Variant>() -> G {
+    let mut rng = rng_from_seed(MIDGAME_SEED);
+    loop {
+        let mut game = G::new_game();
+        for _ in 0..MIDGAME_PLIES {
+            match random_move(&game, &mut rng) {
+                Some(mv) => game.apply_move(mv).expect("legal move rejected"),
+                None => break,
+            }
+            if game.outcome().is_some() {
+                break;
+            }
+        }
+        if game.outcome().is_none() && game.move_count() as usize == MIDGAME_PLIES {
+            return game;
+        }
+    }
+}
+
+fn pieces_on_board<G: Variant>(game: &G) -> usize {
+    game.count_pieces(CellType::Attacker)
+        + game.count_pieces(CellType::Defender)
+        + game.count_pieces(CellType::King)
+}
+
+/// First position (following random play) with a move that captures, and that move.
+fn capture_position<G: Variant>() -> (G, Move) {
+    let mut rng = rng_from_seed(MIDGAME_SEED);
+    loop {
+        let mut game = G::new_game();
+        while game.outcome().is_none() {
+            let before = pieces_on_board(&game);
+            for mv in game.legal_moves() {
+                let mut next = game.clone();
+                next.apply_move(mv).expect("legal move rejected");
+                if pieces_on_board(&next) < before {
+                    return (game, mv);
+                }
+            }
+            match random_move(&game, &mut rng) {
+                Some(mv) => game.apply_move(mv).expect("legal move rejected"),
+                None => break,
+            }
+        }
+    }
+}
+
+/// First legal move from the starting position that captures nothing.
+fn quiet_move<G: Variant>(game: &G) -> Move {
+    let before = pieces_on_board(game);
+    game.legal_moves()
+        .into_iter()
+        .find(|&mv| {
+            let mut next = game.clone();
+            next.apply_move(mv).expect("legal move rejected");
+            pieces_on_board(&next) == before
+        })
+        .expect("no quiet move available")
+}
+
+fn bench_variant<G: Variant>(c: &mut Criterion) {
+    let start = G::new_game();
+    let mid = midgame::<G>();
+    // Busiest piece of each position, so the single-piece benchmark is not trivially empty.
+    let busiest = |game: &G| {
+        game.legal_moves()
+            .iter()
+            .map(|mv| mv.from)
+            .max_by_key(|&from| game.valid_moves_from(from).len())
+            .expect("no legal moves")
+    };
+
+    let mut group = c.benchmark_group(format!("{}/calculate_valid_moves", G::NAME));
+    for (name, game) in [("start", &start), ("midgame", &mid)] {
+        let from = busiest(game);
+        group.bench_with_input(BenchmarkId::from_parameter(name), game, |b, game| {
+            b.iter(|| game.valid_moves_from(black_box(from)))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group(format!("{}/legal_moves", G::NAME));
+    for (name, game) in [("start", &start), ("midgame", &mid)] {
+        group.bench_with_input(BenchmarkId::from_parameter(name), game, |b, game| {
+            b.iter(|| black_box(game).legal_moves())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group(format!("{}/make_move", G::NAME));
+    let quiet = quiet_move(&start);
+    group.bench_function("quiet", |b| {
+        b.iter_batched(
+            || start.clone(),
+            |mut game| game.apply_move(black_box(quiet)),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    let (capture_game, capture) = capture_position::<G>();
+    group.bench_function("capture", |b| {
+        b.iter_batched(
+            || capture_game.clone(),
+            |mut game| game.apply_move(black_box(capture)),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+
+    // Every iteration replays the same seeded game.
+    c.bench_function(&format!("{}/random_playout", G::NAME), |b| {
+        b.iter_batched(
+            || (start.clone(), rng_from_seed(PLAYOUT_SEED)),
+            |(mut game, mut rng)| random_playout(&mut game, &mut rng),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Fixed-depth search from the mid-game position, so node counts do not depend on timing.
+/// Each search gets a fresh engine (and empty transposition table), allocated outside the timing.
+fn bench_search<G: Variant>(c: &mut Criterion, depth: u32) {
+    let mid = midgame::<G>();
+    let mut group = c.benchmark_group(format!("{}/search", G::NAME));
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new("depth", depth), |b| {
+        b.iter_batched(
+            || SearchEngine::<G, _>::new(SimpleEvaluator),
+            |mut engine| engine.search(&mid, &SearchLimits::depth(depth)),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    bench_variant::<brandubh::GameState>(c);
+    bench_variant::<copenhagen::GameState>(c);
+    bench_search::<brandubh::GameState>(c, 4);
+    bench_search::<copenhagen::GameState>(c, 3);
+}
+
+criterion_group!(engine, benches);
+criterion_main!(engine);
@@ -1,22 +1,52 @@
 use std::collections::HashMap;
-use std::io::{self, Write, Read};
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
 use std::net::TcpStream;
 use serde::{Serialize, Deserialize};
-use rand::random_range;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
-#[derive(Serialize, Deserialize, Debug)]
+// Messages carry their kind in a `type` field, e.g. {"type":"move","from":[6,3],"to":[6,1]}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename = "move")]
 struct Move {
     from: (usize, usize),
     to: (usize, usize),
 }
 
+// The first message tells the server who the client is and what it plays
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename = "hello")]
+struct Hello {
+    protocol_version: u32,
+    name: String,
+    variants: Vec<String>,
+    features: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
-struct ServerMessage {
-    message: Option<String>,
-    role: Option<String>,
-    board_state: Option<BoardState>,
-    current_turn: Option<String>,
-    winner: Option<String>,
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Welcome {
+        server: String,
+        variant: String,
+    },
+    GameStarted {
+        role: String,
+    },
+    GameState {
+        board_state: BoardState,
+        current_turn: String,
+        winner: Option<String>,
+        // Sent because the hello asks for the `legal_moves` feature
+        #[serde(default)]
+        legal_moves: Vec<Move>,
+    },
+    Error {
+        message: String,
+    },
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -25,177 +55,78 @@ struct BoardState {
 }
 
 fn main() -> io::Result<()> {
+    // Pass a seed as the first argument to replay a previous game's random choices
+    let seed: u64 = env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or_else(rand::random);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    println!("Random seed: {}", seed);
+
     let mut stream = TcpStream::connect("127.0.0.1:7878")?;
     println!("Connected to the server");
 
-    let mut buffer = [0; 4096];
-    let n = stream.read(&mut buffer)?;
-    let response = String::from_utf8_lossy(&buffer[..n]);
-    println!("Server response: {}", response);
-
-    let mut server_message: ServerMessage = ServerMessage {
-        message: None,
-        role: None,
-        board_state: None,
-        current_turn: None,
-        winner: None,
+    let hello = Hello {
+        protocol_version: 1,
+        name: "client_example_copenhagen".to_string(),
+        variants: vec!["copenhagen".to_string()],
+        features: vec!["legal_moves".to_string()],
     };
+    writeln!(stream, "{}", serde_json::to_string(&hello).unwrap())?;
 
-    for part in response.split("}{") {
-        let json_str = if part.starts_with('{') && part.ends_with('}') {
-            part.to_string()
-        } else if part.starts_with('{') {
-            format!("{}{}", part, "}")
-        } else {
-            format!("{}{}", "{", part)
-        };
-
-        match serde_json::from_str(&json_str) {
-            Ok(msg) => server_message = msg,
-            Err(e) => {
-                eprintln!("Failed to parse server message: {}", e);
-                return Ok(());
-            }
-        }
-    }
-
+    // The server sends one JSON message per line
+    let mut reader = BufReader::new(stream.try_clone()?);
     let mut player_role = String::new();
-
-    if let Some(message) = server_message.message {
-        if message == "Game has started" {
-            if let Some(role) = server_message.role {
-                player_role = role.clone();
-                if role == "Attacker" {
-                    if let Some(board_state) = server_message.board_state.clone() {
-                        send_move(&mut stream, &board_state.board, &role)?;
-                    }
-                } else {
-                    println!("Waiting for the opponent's move...");
-                }
-            }
-        }
-    }
+    let mut line = String::new();
 
     loop {
-        let n = stream.read(&mut buffer)?;
-        let response = String::from_utf8_lossy(&buffer[..n]);
-
-        for part in response.split("}{") {
-            let json_str = if part.starts_with('{') && part.ends_with('}') {
-                part.to_string()
-            } else if part.starts_with('{') {
-                format!("{}{}", part, "}")
-            } else {
-                format!("{}{}", "{", part)
-            };
-
-            match serde_json::from_str(&json_str) {
-                Ok(msg) => server_message = msg,
-                Err(e) => {
-                    eprintln!("Failed to parse server message: {}", e);
-                    continue;
-                }
-            }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            println!("Server closed the connection");
+            return Ok(());
         }
-
-        if let Some(winner) = server_message.winner.clone() {
-            println!("Game over! The winner is: {}", winner);
+        if line.trim().is_empty() {
+            continue;
         }
 
-        if let Some(board_state) = server_message.board_state.clone() {
-            println!("Board state: {:?}", board_state.board);
-        }
+        let server_message: ServerMessage = match serde_json::from_str(&line) {
+            Ok(msg) => msg,
+            Err(e) => {
+                eprintln!("Failed to parse server message: {}", e);
+                continue;
+            }
+        };
 
-        if let Some(current_turn) = server_message.current_turn.clone() {
-            if current_turn == player_role && server_message.winner.is_none() {
-                if let Some(ref board_state) = server_message.board_state {
-                    send_move(&mut stream, &board_state.board, &player_role)?;
+        match server_message {
+            ServerMessage::Welcome { server, variant } => println!("Welcome from {}, playing {}", server, variant),
+            ServerMessage::GameStarted { role } => {
+                println!("Game has started, playing {}", role);
+                player_role = role;
+            }
+            ServerMessage::GameState { board_state, current_turn, winner, legal_moves } => {
+                println!("Board state: {:?}", board_state.board);
+                if let Some(winner) = winner {
+                    println!("Game over! The winner is: {}", winner);
+                } else if current_turn == player_role {
+                    send_move(&mut stream, &legal_moves, &mut rng)?;
                 } else {
-                    println!("No board state available for the current turn.");
+                    println!("Waiting for the opponent's move...");
                 }
-            } else {
-                println!("Waiting for the opponent's move...");
             }
+            ServerMessage::Error { message } => eprintln!("Server error: {}", message),
+            ServerMessage::Other => {}
         }
     }
 }
 
-fn send_move(stream: &mut TcpStream, board: &HashMap<String, String>, role: &str) -> io::Result<()> {
-    let mut pieces: Vec<(usize, usize)> = Vec::new();
-
-    // Collect all pieces of the current player
-    for (pos, piece) in board {
-        let coords: Vec<usize> = pos[1..pos.len()-1].split(", ").map(|s| s.parse().unwrap()).collect();
-        if (role == "Attacker" && piece == "Attacker") || (role == "Defender" && (piece == "Defender" || piece == "King")) {
-            pieces.push((coords[0], coords[1]));
-        }
-    }
-
-    // Find a random valid move
-    loop {
-        // Select a random piece
-        let piece = pieces[random_range(0..pieces.len())];
-        let directions = vec![(1, 0), (-1, 0), (0, 1), (0, -1)];
-        let direction = directions[random_range(0..directions.len())];
-
-        // Start from the piece's position
-        let mut to = (piece.0 as isize, piece.1 as isize);
-
-        // Collect all valid cells in the chosen direction
-        let mut valid_positions = vec![];
-        for _ in 1..6 {
-            to = (to.0 + direction.0, to.1 + direction.1);
-
-            // Check if the move is out of bounds
-            if to.0 < 0 || to.0 >= 10 || to.1 < 0 || to.1 >= 10 {
-                break;
-            }
-
-            let piece_role = board.get(&format!("({}, {})", piece.0, piece.1)).unwrap().clone();
-            if !(role == "Defender" && piece_role == "King") {
-                // Check if the destination is a corner or the middle of the board
-                if (to.0 == 0 && to.1 == 0)
-                    || (to.0 == 0 && to.1 == 10)
-                    || (to.0 == 10 && to.1 == 0)
-                    || (to.0 == 10 && to.1 == 10)
-                    || (to.0 == 5 && to.1 == 5)
-                {
-                    break;
-                }
-            } 
-
-            // Check if the cell is empty
-            if board
-                .get(&format!("({}, {})", to.0, to.1))
-                .unwrap_or(&"Empty".to_string())
-                != "Empty"
-            {
-                break;
-            }
-
-            // Add this position to the list of valid positions
-            valid_positions.push((to.0 as usize, to.1 as usize));
-        }
-
-        // If there are no valid positions, continue to the next piece/direction
-        if valid_positions.is_empty() {
-            continue;
-        }
-
-        // Choose a random valid position to move to
-        let random_destination = valid_positions[random_range(0..valid_positions.len())];
-
-        // Send the move to the server
-        let game_move = Move {
-            from: piece,
-            to: random_destination,
-        };
-
-        let serialized_move = serde_json::to_string(&game_move).unwrap();
-        stream.write_all(serialized_move.as_bytes())?;
-        println!("Move sent to the server: {:?}", game_move);
+// The server lists the legal moves, so the client only has to pick one; the
+// list comes in a fixed order, so the seed alone decides the move
+fn send_move(stream: &mut TcpStream, legal_moves: &[Move], rng: &mut ChaCha8Rng) -> io::Result<()> {
+    if legal_moves.is_empty() {
+        println!("No legal move available");
         return Ok(());
     }
+    let game_move = &legal_moves[rng.random_range(0..legal_moves.len())];
 
-
-}
\ No newline at end of file
+    let serialized_move = serde_json::to_string(game_move).unwrap();
+    writeln!(stream, "{}", serialized_move)?;
+    println!("Move sent to the server: {:?}", game_move);
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! Client that lets one of the crate's agents play on the server.
+//!
+//! Unlike the standalone `client_example_*.rs` files, it uses the crate's own
+//! rules engine, so it never sends an illegal move.
+//!
+//! ```sh
+//! cargo run --example agent_client -- [SEED] [BOT[:LEVEL] [ROLE]]
+//! ```
+//!
+//! With `BOT` (`random`, `search`, `mcts` or `engine:NAME` for an external
+//! engine registered on the server) it asks the server for a bot opponent
+//! instead of waiting for another client, playing `ROLE` (`Attacker` by default).
+//! The client introduces itself with a `hello` first, so its name shows up in
+//! the server's session results.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+use hnefatafl::agent::{Agent, Clock, RandomAgent};
+use hnefatafl::cell::CellType;
+use hnefatafl::protocol::{self, BotRequest, ClientMessage, Hello, LevelValue, ServerMessage};
+use hnefatafl::rng;
+use hnefatafl::variant::Variant;
+
+// 7x7 version
+use hnefatafl::brandubh::GameState;
+
+// 11x11 version
+//use hnefatafl::copenhagen::GameState;
+
+fn main() -> io::Result<()> {
+    let seed: u64 = env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or_else(rng::random_seed);
+    let mut agent = RandomAgent::new(seed);
+    println!("Random seed: {}", seed);
+
+    let mut stream = TcpStream::connect("127.0.0.1:7878")?;
+    println!("Connected to the server");
+
+    let preferred_role = match env::args().nth(3).as_deref() {
+        Some("Defender") | Some("defender") => Some(CellType::Defender),
+        Some("Attacker") | Some("attacker") => Some(CellType::Attacker),
+        _ => None,
+    };
+    let hello = Hello {
+        variants: vec![GameState::NAME.to_string()],
+        preferred_role,
+        ..Hello::new("agent_client", env!("CARGO_PKG_VERSION"))
+    };
+    stream.write_all(protocol::to_line(&ClientMessage::Hello(hello)).as_bytes())?;
+
+    if let Some(bot) = env::args().nth(2) {
+        let mut parts = bot.split(':');
+        let name = parts.next().unwrap_or_default().to_string();
+        let engine = if name == "engine" { parts.next().map(str::to_string) } else { None };
+        // A level number or name, e.g. search:2 or search:easy
+        let level = parts.next().map(|level| match level.parse::<u32>() {
+            Ok(number) => LevelValue::Number(number),
+            Err(_) => LevelValue::Name(level.to_string()),
+        });
+        let role = preferred_role.unwrap_or(CellType::Attacker);
+        let request = ClientMessage::PlayBot(BotRequest { bot: name.clone(), name: engine, level, role: Some(role) });
+        stream.write_all(protocol::to_line(&request).as_bytes())?;
+        println!("Requested a {} bot opponent, playing {}", name, role);
+    }
+
+    let mut role = None;
+
+    // The server sends one message per line
+    for line in BufReader::new(stream.try_clone()?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = match protocol::from_line::<ServerMessage>(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("Failed to parse server message: {}", e);
+                continue;
+            }
+        };
+
+        let (board_state, current_turn) = match message {
+            ServerMessage::Welcome(welcome) => {
+                println!("Welcome from {}, playing {}", welcome.server, welcome.variant);
+                continue;
+            }
+            ServerMessage::GameStarted { role: started } => {
+                println!("Game has started, playing {}", started);
+                role = Some(started);
+                continue;
+            }
+            ServerMessage::Error { message } => {
+                eprintln!("Server error: {}", message);
+                continue;
+            }
+            ServerMessage::Analysis(_) => continue,
+            ServerMessage::GameState { winner: Some(winner), .. } => {
+                println!("Game over! The winner is: {}", winner);
+                continue;
+            }
+            ServerMessage::GameState { board_state, current_turn, winner: None, .. } => (board_state, current_turn),
+        };
+        if Some(current_turn) != role {
+            continue;
+        }
+
+        let position: GameState = board_state
+            .to_position(current_turn)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !position.has_legal_move() {
+            println!("No legal move available");
+            continue;
+        }
+        let mv = agent.choose_move(&position, &Clock::unlimited());
+        stream.write_all(protocol::to_line(&ClientMessage::Move(mv)).as_bytes())?;
+        println!("Move sent to the server: {:?}", mv);
+    }
+    println!("Server closed the connection");
+    Ok(())
+}
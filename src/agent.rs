@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::rng::{self, GameRng};
+use crate::variant::{Move, Variant};
+
+/// Time available to an agent for the move it is asked to choose.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Clock {
+    pub remaining: Option<Duration>, // Time left for the rest of the game
+    pub per_move: Option<Duration>,  // Time allowed for this move
+}
+
+impl Clock {
+    /// No time limit at all.
+    pub fn unlimited() -> Self {
+        Clock::default()
+    }
+
+    pub fn per_move(time: Duration) -> Self {
+        Clock {
+            remaining: None,
+            per_move: Some(time),
+        }
+    }
+
+    /// Time the agent should spend on this move: the per-move limit, capped by
+    /// a share of the remaining game time. `None` when there is no limit.
+    pub fn budget(&self) -> Option<Duration> {
+        let from_remaining = self.remaining.map(|remaining| remaining / 20);
+        match (self.per_move, from_remaining) {
+            (Some(per_move), Some(share)) => Some(per_move.min(share)),
+            (per_move, share) => per_move.or(share),
+        }
+    }
+}
+
+/// A player that picks moves for either side of a game.
+pub trait Agent<G: Variant>: Send {
+    /// Name used in logs and results.
+    fn name(&self) -> String;
+
+    /// Picks a move for the side to move in `position`. Only called when that
+    /// side has at least one legal move.
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move;
+
+    /// Like `choose_move`, for agents that can fail to pick a move (such as an
+    /// external engine that exits). Agents that cannot fail just pick one.
+    fn try_choose_move(&mut self, position: &G, clock: &Clock) -> Result<Move, String> {
+        Ok(self.choose_move(position, clock))
+    }
+
+    /// How the agent weighed the moves of its last `choose_move`, for agents
+    /// that keep such a distribution (MCTS root visit counts). Moves left out
+    /// have no weight.
+    fn move_weights(&self) -> Option<Vec<(Move, u32)>> {
+        None
+    }
+}
+
+/// Lets boxed agents (such as players chosen at run time) be wrapped by other agents.
+impl<G: Variant, A: Agent<G> + ?Sized> Agent<G> for Box<A> {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move {
+        (**self).choose_move(position, clock)
+    }
+
+    fn try_choose_move(&mut self, position: &G, clock: &Clock) -> Result<Move, String> {
+        (**self).try_choose_move(position, clock)
+    }
+
+    fn move_weights(&self) -> Option<Vec<(Move, u32)>> {
+        (**self).move_weights()
+    }
+}
+
+/// Plays a uniformly random legal move.
+pub struct RandomAgent {
+    seed: u64,
+    rng: GameRng,
+}
+
+impl RandomAgent {
+    pub fn new(seed: u64) -> Self {
+        RandomAgent {
+            seed,
+            rng: rng::rng_from_seed(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl<G: Variant> Agent<G> for RandomAgent {
+    fn name(&self) -> String {
+        "random".to_string()
+    }
+
+    fn choose_move(&mut self, position: &G, _clock: &Clock) -> Move {
+        let moves = position.legal_moves();
+        moves[self.rng.random_range(0..moves.len())]
+    }
+}
@@ -0,0 +1,81 @@
+//! Position analysis outside of play: the engine's best move, its score and
+//! principal variation, and the search statistics, optionally for the best
+//! few moves (multi-PV).
+//!
+//! Scores are from the point of view of the side to move, as in `search`.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cell::CellType;
+use crate::eval::{WeightedEvaluator, Weights};
+use crate::search::{Evaluator, SearchEngine, SearchLimits};
+use crate::variant::{Move, Variant};
+
+/// One of the ranked moves of an analysis.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalysisLine {
+    #[serde(rename = "move")]
+    pub mv: Move,
+    pub score: i32,
+    pub depth: u32,    // Last depth completed for this line
+    pub pv: Vec<Move>, // Principal variation, starting with `mv`
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Analysis {
+    pub side: Option<CellType>, // Side to move; `None` if the game is over
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub lines: Vec<AnalysisLine>, // Best first; the first one is `best_move`
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+/// Analyses `position` with `engine`, ranking the best `multi_pv` moves (at
+/// least one). Each line is a search of its own under `limits`, with the
+/// moves of the lines before it excluded, so a time limit applies per line.
+pub fn analyse_with<G: Variant, E: Evaluator<G>>(
+    engine: &mut SearchEngine<G, E>,
+    position: &G,
+    limits: &SearchLimits,
+    multi_pv: usize,
+) -> Analysis {
+    let mut analysis = Analysis::default();
+    if position.outcome().is_some() {
+        return analysis;
+    }
+    analysis.side = Some(position.side_to_move());
+
+    let mut excluded = Vec::new();
+    while excluded.len() < multi_pv.max(1) {
+        let info = engine.search_excluding(position, limits, &excluded);
+        analysis.nodes += info.nodes;
+        analysis.elapsed += info.elapsed;
+        let Some(mv) = info.best_move else {
+            break;
+        };
+        excluded.push(mv);
+        analysis.lines.push(AnalysisLine {
+            mv,
+            score: info.score,
+            depth: info.depth,
+            pv: info.pv,
+        });
+    }
+
+    if let Some(best) = analysis.lines.first() {
+        analysis.best_move = Some(best.mv);
+        analysis.score = best.score;
+        analysis.pv = best.pv.clone();
+    }
+    analysis
+}
+
+/// Analyses `position` with a fresh engine using the default `Weights`.
+pub fn analyse<G: Variant>(position: &G, limits: &SearchLimits, multi_pv: usize) -> Analysis {
+    let mut engine = SearchEngine::new(WeightedEvaluator::new(Weights::default()));
+    analyse_with(&mut engine, position, limits, multi_pv)
+}
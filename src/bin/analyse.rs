@@ -0,0 +1,204 @@
+//! Analyses a position: best move, score, principal variation and search
+//! statistics, for the best N moves with `--multi-pv N`.
+//!
+//! ```sh
+//! cargo run --release --bin analyse -- --variant brandubh --depth 5 --multi-pv 3 \
+//!     --position "...A.../...A.../...D.../AADKDAA/...D.../...A.../...A... a"
+//! ```
+//!
+//! Positions use the text notation of `src/encoding.rs`; the start position
+//! is analysed if none is given. The text output ends with the tactics of
+//! `src/tactics.rs`: king escape routes and capture threats. `--json` prints
+//! the analysis as JSON.
+//!
+//! `--solve N` instead proves whether the side to move can force a win within
+//! N of its moves and prints the winning line; `--nodes` then limits the solver.
+
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+use hnefatafl::analysis::{self, Analysis};
+use hnefatafl::brandubh;
+use hnefatafl::cell::CellType;
+use hnefatafl::copenhagen;
+use hnefatafl::encoding;
+use hnefatafl::eval::{WeightedEvaluator, Weights};
+use hnefatafl::search::{SearchEngine, SearchLimits};
+use hnefatafl::solver::{SolveResult, Solver};
+use hnefatafl::tactics;
+use hnefatafl::variant::{Move, Variant};
+
+const USAGE: &str = "Usage: analyse [--variant brandubh|copenhagen] [--position TEXT] [--depth N]
+               [--time MS] [--nodes N] [--multi-pv N] [--weights FILE] [--json] [--solve N]
+               [--threads N]";
+
+struct Config {
+    variant: String,
+    position: Option<String>,
+    limits: SearchLimits,
+    multi_pv: usize,
+    weights: Weights,
+    json: bool,
+    solve: Option<u32>,
+    threads: usize,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        variant: "brandubh".to_string(),
+        position: None,
+        limits: SearchLimits::depth(4),
+        multi_pv: 1,
+        weights: Weights::default(),
+        json: false,
+        solve: None,
+        threads: 1,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "--json" {
+            config.json = true;
+            continue;
+        }
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--variant" => config.variant = value,
+            "--position" => config.position = Some(value),
+            "--depth" => config.limits.max_depth = value.parse().map_err(|_| format!("Invalid depth: {}", value))?,
+            "--time" => {
+                let millis = value.parse().map_err(|_| format!("Invalid time: {}", value))?;
+                config.limits.time = Some(Duration::from_millis(millis));
+            }
+            "--nodes" => config.limits.nodes = Some(value.parse().map_err(|_| format!("Invalid node count: {}", value))?),
+            "--multi-pv" => config.multi_pv = value.parse().map_err(|_| format!("Invalid line count: {}", value))?,
+            "--weights" => config.weights = Weights::load(&value)?,
+            "--threads" => config.threads = value.parse().map_err(|_| format!("Invalid thread count: {}", value))?,
+            "--solve" => config.solve = Some(value.parse().map_err(|_| format!("Invalid move count: {}", value))?),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    Ok(config)
+}
+
+fn format_move(mv: &Move) -> String {
+    format!("{:?}->{:?}", mv.from, mv.to)
+}
+
+fn print_analysis(analysis: &Analysis) {
+    let Some(side) = analysis.side else {
+        println!("The game is over");
+        return;
+    };
+    println!("{} to move", side);
+    for (index, line) in analysis.lines.iter().enumerate() {
+        let pv: Vec<String> = line.pv.iter().map(format_move).collect();
+        println!(
+            "{:>2}. {:<16} score {:>8}  depth {:>2}  pv {}",
+            index + 1,
+            format_move(&line.mv),
+            line.score,
+            line.depth,
+            pv.join(" ")
+        );
+    }
+    if analysis.lines.is_empty() {
+        println!("No legal move");
+    }
+    println!(
+        "{} nodes in {:.2?} ({:.0} nodes/s)",
+        analysis.nodes,
+        analysis.elapsed,
+        analysis.nodes as f64 / analysis.elapsed.as_secs_f64().max(1e-9)
+    );
+}
+
+fn print_tactics<G: Variant>(position: &G) {
+    let moves = |moves: &[Move]| moves.iter().map(format_move).collect::<Vec<_>>().join(" ");
+    let routes: Vec<String> = tactics::escape_routes(position)
+        .iter()
+        .map(|route| format!("{}=>{:?}", format_move(&route.king_move), route.corner))
+        .collect();
+    println!("King escape routes: {}", routes.join(" "));
+    if tactics::is_forced_escape(position) {
+        println!("The king escapes by force");
+    }
+    let forcing = tactics::forcing_king_moves(position);
+    if !forcing.is_empty() {
+        println!("Forcing king moves: {}", moves(&forcing));
+    }
+    let king_threats = tactics::king_capture_threats(position);
+    if !king_threats.is_empty() {
+        println!("King capture threats: {}", moves(&king_threats));
+    }
+    for side in [CellType::Attacker, CellType::Defender] {
+        for threat in tactics::threatened_pieces(position, side) {
+            println!("{} on {:?} threatened by {}", side, threat.square, moves(&threat.moves));
+        }
+    }
+}
+
+fn solve<G: Variant>(position: &G, max_moves: u32, node_limit: Option<u64>) {
+    println!("{}: {}", G::NAME, encoding::position_to_text(position));
+    let mut solver = Solver::new(node_limit);
+    let start = Instant::now();
+    match solver.solve(position, max_moves) {
+        SolveResult::Win { moves, line } => {
+            let line: Vec<String> = line.iter().map(format_move).collect();
+            println!("{} wins in {}: {}", position.side_to_move(), moves, line.join(" "));
+        }
+        SolveResult::NoWin => println!("No forced win in {} moves", max_moves),
+        SolveResult::Unknown => println!("Node limit reached before a result"),
+    }
+    println!("{} nodes in {:.2?}", solver.nodes(), start.elapsed());
+}
+
+fn run<G: Variant>(config: &Config) -> Result<(), String> {
+    let position = match &config.position {
+        Some(text) => encoding::position_from_text::<G>(text)?,
+        None => G::new_game(),
+    };
+    if let Some(max_moves) = config.solve {
+        solve(&position, max_moves, config.limits.nodes);
+        return Ok(());
+    }
+    let mut engine = SearchEngine::new(WeightedEvaluator::new(config.weights));
+    engine.set_threads(config.threads);
+    let analysis = analysis::analyse_with(&mut engine, &position, &config.limits, config.multi_pv);
+
+    if config.json {
+        println!("{}", serde_json::to_string(&analysis).map_err(|e| e.to_string())?);
+    } else {
+        println!("{}: {}", G::NAME, encoding::position_to_text(&position));
+        print_analysis(&analysis);
+        if analysis.side.is_some() {
+            print_tactics(&position);
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match config.variant.as_str() {
+        "brandubh" => run::<brandubh::GameState>(&config),
+        "copenhagen" => run::<copenhagen::GameState>(&config),
+        other => {
+            eprintln!("Unknown variant: {}\n{}", other, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,113 @@
+//! Builds an opening book from game archives.
+//!
+//! ```sh
+//! cargo run --release --bin book -- --variant brandubh --archive results/games.bin --output results/book.bin
+//! ```
+//!
+//! Archives are written by `simulate --archive`; `--archive` can be given
+//! several times. Unfinished games are skipped.
+
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::process;
+
+use hnefatafl::book::BookBuilder;
+use hnefatafl::brandubh;
+use hnefatafl::copenhagen;
+use hnefatafl::encoding;
+use hnefatafl::variant::Variant;
+
+const USAGE: &str = "Usage: book [--variant brandubh|copenhagen] --archive FILE [--archive FILE ...]
+            [--plies N] [--min-games N] [--output FILE]";
+
+struct Config {
+    variant: String,
+    archives: Vec<String>,
+    plies: usize,
+    min_games: u32,
+    output: String,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        variant: "brandubh".to_string(),
+        archives: Vec::new(),
+        plies: 16,
+        min_games: 1,
+        output: "results/book.bin".to_string(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--variant" => config.variant = value,
+            "--archive" => config.archives.push(value),
+            "--plies" => config.plies = value.parse().map_err(|_| format!("Invalid ply count: {}", value))?,
+            "--min-games" => config.min_games = value.parse().map_err(|_| format!("Invalid game count: {}", value))?,
+            "--output" => config.output = value,
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    if config.archives.is_empty() {
+        return Err("At least one --archive is required".to_string());
+    }
+    Ok(config)
+}
+
+fn run<G: Variant>(config: &Config) -> Result<(), String> {
+    let mut builder = BookBuilder::<G>::new(config.plies);
+    let mut skipped = 0;
+    for archive in &config.archives {
+        let file = File::open(archive).map_err(|e| format!("Cannot read {}: {}", archive, e))?;
+        let mut reader = BufReader::new(file);
+        let mut index = 0;
+        while let Some(record) = encoding::read_record(&mut reader).map_err(|e| format!("{}: {}", archive, e))? {
+            index += 1;
+            let (start, moves) =
+                encoding::decode_game::<G>(&record).map_err(|e| format!("{}, game {}: {}", archive, index, e))?;
+            if !builder.add_game(&start, &moves).map_err(|e| format!("{}, game {}: {}", archive, index, e))? {
+                skipped += 1;
+            }
+        }
+    }
+
+    let book = builder.build(config.min_games);
+    println!(
+        "{} {} games ({} unfinished skipped): {} book moves in {} positions",
+        builder.games(),
+        G::NAME,
+        skipped,
+        book.len(),
+        book.positions()
+    );
+    book.save(&config.output)
+        .map_err(|e| format!("Cannot write {}: {}", config.output, e))?;
+    println!("Book written to {}", config.output);
+    Ok(())
+}
+
+fn main() {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match config.variant.as_str() {
+        "brandubh" => run::<brandubh::GameState>(&config),
+        "copenhagen" => run::<copenhagen::GameState>(&config),
+        other => {
+            eprintln!("Unknown variant: {}\n{}", other, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,44 @@
+//! Runs the built-in search engine on the engine protocol of
+//! `hnefatafl::engine` over stdin/stdout.
+//!
+//! ```sh
+//! cargo run --release --bin engine -- [--weights FILE] [--threads N]
+//! ```
+
+use std::env;
+use std::io;
+use std::process;
+
+use hnefatafl::engine;
+use hnefatafl::eval::Weights;
+
+const USAGE: &str = "Usage: engine [--weights FILE] [--threads N]";
+
+fn parse_args() -> Result<(Weights, usize), String> {
+    let mut weights = Weights::default();
+    let mut threads = 1;
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--weights" => weights = Weights::load(&value)?,
+            "--threads" => threads = value.parse().map_err(|_| format!("Invalid thread count: {}", value))?,
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    Ok((weights, threads))
+}
+
+fn main() {
+    let (weights, threads) = match parse_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = engine::run_engine(io::stdin().lock(), io::stdout().lock(), weights, threads) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,513 @@
+//! In-process self-play simulator.
+//!
+//! Plays N games between two built-in players on all CPU cores and writes the
+//! per-game outcomes plus the same aggregate statistics the server collects.
+//!
+//! ```sh
+//! cargo run --release --bin simulate -- --variant brandubh --games 10000
+//! ```
+//!
+//! Every game gets its own seed, derived from the run seed (`--seed`) and the
+//! game number and logged with its outcome. `--replay SEED` replays one game.
+//! With `--sides random` the game seed also decides whether the two players
+//! swap sides, so that both play each side about equally often.
+//!
+//! With `--tablebase FILE` (Brandubh only) both players play perfectly once the
+//! position is in the endgame tablebase.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use hnefatafl::agent::{Agent, Clock, RandomAgent};
+use hnefatafl::book::{BookAgent, OpeningBook};
+use hnefatafl::brandubh;
+use hnefatafl::cell::CellType;
+use hnefatafl::copenhagen;
+use hnefatafl::dataset::{self, DatasetSummary, DatasetWriter};
+use hnefatafl::encoding;
+use hnefatafl::eval::{WeightedEvaluator, Weights};
+use hnefatafl::mcts::{CaptureGreedyRollout, HeuristicRollout, MctsAgent, MctsConfig, RandomRollout};
+use hnefatafl::rng;
+use hnefatafl::search::{SearchAgent, SearchLimits, SimpleEvaluator};
+use hnefatafl::stats::{GameRecord, GameStats};
+use hnefatafl::strength::{Level, StrengthAgent};
+use hnefatafl::tablebase::{Tablebase, TablebaseAgent};
+use hnefatafl::variant::{Move, Variant};
+
+const USAGE: &str = "Usage: simulate [--variant brandubh|copenhagen] [--games N] [--threads N]
+                [--attacker PLAYER] [--defender PLAYER] [--output PREFIX]
+                [--seed SEED] [--replay GAME_SEED] [--archive FILE] [--weights FILE]
+                [--sides fixed|random] [--book FILE] [--tablebase FILE] [--export DIR]
+                [--chunk-size N]
+Players: random, search (depth 3), search:DEPTH,
+         mcts (1000 playouts, heuristic rollouts), mcts:PLAYOUTS[:random|greedy|heuristic],
+         level:beginner|easy|medium|hard|expert (or level:1 to level:5)";
+
+/// Built-in agents selectable from the command line.
+#[derive(Debug, Clone, Copy)]
+enum Player {
+    Random,
+    Search(u32),        // Alpha-beta search to a fixed depth
+    Mcts(u64, Rollout), // MCTS with a fixed number of playouts per move
+    Level(Level),       // Search engine playing at a difficulty level
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Rollout {
+    Random,
+    Greedy,
+    Heuristic,
+}
+
+impl Player {
+    fn parse(name: &str) -> Option<Player> {
+        match name {
+            "random" => Some(Player::Random),
+            "search" => Some(Player::Search(3)),
+            "mcts" => Some(Player::Mcts(1000, Rollout::Heuristic)),
+            _ => {
+                if let Some(depth) = name.strip_prefix("search:") {
+                    return Some(Player::Search(depth.parse().ok()?));
+                }
+                if let Some(level) = name.strip_prefix("level:") {
+                    let level = Level::from_name(level).or_else(|| Level::from_number(level.parse().ok()?))?;
+                    return Some(Player::Level(level));
+                }
+                let mut parts = name.strip_prefix("mcts:")?.split(':');
+                let playouts = parts.next()?.parse().ok()?;
+                let rollout = match parts.next() {
+                    None | Some("heuristic") => Rollout::Heuristic,
+                    Some("random") => Rollout::Random,
+                    Some("greedy") => Rollout::Greedy,
+                    Some(_) => return None,
+                };
+                parts.next().is_none().then_some(Player::Mcts(playouts, rollout))
+            }
+        }
+    }
+
+    /// Search players use `weights` with a `WeightedEvaluator` if given, `SimpleEvaluator` otherwise.
+    fn create<G: Variant>(&self, seed: u64, weights: Option<Weights>) -> Box<dyn Agent<G>> {
+        match self {
+            Player::Random => Box::new(RandomAgent::new(seed)),
+            Player::Search(depth) => match weights {
+                Some(weights) => Box::new(SearchAgent::new(WeightedEvaluator::new(weights), SearchLimits::depth(*depth))),
+                None => Box::new(SearchAgent::new(SimpleEvaluator, SearchLimits::depth(*depth))),
+            },
+            Player::Mcts(playouts, rollout) => {
+                let config = MctsConfig::playouts(*playouts);
+                match rollout {
+                    Rollout::Random => Box::new(MctsAgent::new(RandomRollout, config, seed)),
+                    Rollout::Greedy => Box::new(MctsAgent::new(CaptureGreedyRollout, config, seed)),
+                    Rollout::Heuristic => Box::new(MctsAgent::new(HeuristicRollout, config, seed)),
+                }
+            }
+            Player::Level(level) => Box::new(StrengthAgent::new(*level, seed)),
+        }
+    }
+}
+
+/// The player's name as given on the command line.
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Player::Random => write!(f, "random"),
+            Player::Search(depth) => write!(f, "search:{}", depth),
+            Player::Mcts(playouts, rollout) => {
+                let rollout = match rollout {
+                    Rollout::Random => "random",
+                    Rollout::Greedy => "greedy",
+                    Rollout::Heuristic => "heuristic",
+                };
+                write!(f, "mcts:{}:{}", playouts, rollout)
+            }
+            Player::Level(level) => write!(f, "level:{}", level),
+        }
+    }
+}
+
+struct Config {
+    variant: String,
+    games: u32,
+    threads: usize,
+    attacker: Player,
+    defender: Player,
+    random_sides: bool, // Whether each game's seed decides if the players swap sides
+    output: String,
+    seed: u64,
+    replay: Option<u64>,
+    archive: Option<String>,
+    export: Option<String>, // Directory for training data
+    chunk_size: usize,
+    weights: Option<Weights>,
+    book: Option<String>,      // Loaded by `run`, once the variant is known
+    tablebase: Option<String>, // Loaded by `run_brandubh`; Brandubh only
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        variant: "brandubh".to_string(),
+        games: 1000,
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        attacker: Player::Random,
+        defender: Player::Random,
+        random_sides: false,
+        output: "results/simulation".to_string(),
+        seed: rng::random_seed(),
+        replay: None,
+        archive: None,
+        export: None,
+        chunk_size: dataset::DEFAULT_CHUNK_SIZE,
+        weights: None,
+        book: None,
+        tablebase: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--variant" => config.variant = value,
+            "--games" => config.games = value.parse().map_err(|_| format!("Invalid game count: {}", value))?,
+            "--threads" => config.threads = value.parse().map_err(|_| format!("Invalid thread count: {}", value))?,
+            "--attacker" => config.attacker = Player::parse(&value).ok_or(format!("Unknown player: {}", value))?,
+            "--defender" => config.defender = Player::parse(&value).ok_or(format!("Unknown player: {}", value))?,
+            "--sides" => {
+                config.random_sides = match value.as_str() {
+                    "fixed" => false,
+                    "random" => true,
+                    _ => return Err(format!("Invalid sides: {} (expected fixed or random)", value)),
+                }
+            }
+            "--output" => config.output = value,
+            "--seed" => config.seed = value.parse().map_err(|_| format!("Invalid seed: {}", value))?,
+            "--archive" => config.archive = Some(value),
+            "--export" => config.export = Some(value),
+            "--chunk-size" => config.chunk_size = value.parse().map_err(|_| format!("Invalid chunk size: {}", value))?,
+            "--weights" => config.weights = Some(Weights::load(&value)?),
+            "--book" => config.book = Some(value),
+            "--tablebase" => config.tablebase = Some(value),
+            "--replay" => config.replay = Some(value.parse().map_err(|_| format!("Invalid seed: {}", value))?),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    if config.threads == 0 {
+        return Err("Thread count must be at least 1".to_string());
+    }
+    Ok(config)
+}
+
+/// Move weights of the agent for each move of a game (see `Agent::move_weights`).
+type MoveWeights = Vec<Option<Vec<(Move, u32)>>>;
+
+/// Wraps each player before the game, e.g. to play endgames from a tablebase.
+type Wrap<G> = dyn Fn(Box<dyn Agent<G>>) -> Box<dyn Agent<G>> + Sync;
+
+/// Whether the `--defender` player takes the attackers in the game with this
+/// seed, which with random sides is so for about half of the games.
+fn sides_swapped(config: &Config, seed: u64) -> bool {
+    config.random_sides && rng::derive_seed(seed, 4) % 2 == 1
+}
+
+/// The players of a game, as (attacker, defender).
+fn game_players(config: &Config, swapped: bool) -> (Player, Player) {
+    if swapped { (config.defender, config.attacker) } else { (config.attacker, config.defender) }
+}
+
+/// Plays one game to the end, timing each move into `stats`, and returns its
+/// outcome together with the moves played and, when exporting training data,
+/// the agents' move weights. Both players are wrapped by `wrap`, and with a
+/// `book` they play book moves while they can.
+fn play_game<G: Variant>(
+    config: &Config,
+    book: Option<&Arc<OpeningBook<G>>>,
+    wrap: &Wrap<G>,
+    seed: u64,
+    stats: &mut GameStats,
+) -> (GameRecord, Vec<Move>, MoveWeights) {
+    // Each side draws from its own stream, so changing one player does not alter the other's choices
+    let (attacker, defender) = game_players(config, sides_swapped(config, seed));
+    let mut attacker_agent = wrap(attacker.create::<G>(rng::derive_seed(seed, 0), config.weights));
+    let mut defender_agent = wrap(defender.create::<G>(rng::derive_seed(seed, 1), config.weights));
+    if let Some(book) = book {
+        attacker_agent = Box::new(BookAgent::new(Arc::clone(book), attacker_agent, rng::derive_seed(seed, 2)));
+        defender_agent = Box::new(BookAgent::new(Arc::clone(book), defender_agent, rng::derive_seed(seed, 3)));
+    }
+    let clock = Clock::unlimited();
+    let mut game = G::new_game();
+    let mut moves = Vec::new();
+    let mut weights = Vec::new();
+    let mut attacker_moves = 0;
+    let mut defender_moves = 0;
+
+    let winner = loop {
+        if let Some(outcome) = game.outcome() {
+            break outcome;
+        }
+        let role = game.side_to_move();
+        let opponent = if role == CellType::Attacker { CellType::Defender } else { CellType::Attacker };
+        let agent = if role == CellType::Attacker { &mut attacker_agent } else { &mut defender_agent };
+
+        // A side without any move loses, even where the variant's rules do not check it
+        if !game.has_legal_move() {
+            break opponent;
+        }
+
+        let move_start_time = Instant::now();
+        let mv = agent.choose_move(&game, &clock);
+        stats.record_move_duration(role, move_start_time.elapsed());
+
+        if let Err(err) = game.play(mv) {
+            eprintln!("Invalid move from {} ({}): {}", role, agent.name(), err);
+            break opponent;
+        }
+        moves.push(mv);
+        if config.export.is_some() {
+            weights.push(agent.move_weights());
+        }
+        if role == CellType::Attacker {
+            attacker_moves += 1;
+        } else {
+            defender_moves += 1;
+        }
+    };
+
+    let record = GameRecord {
+        winner,
+        attacker_moves,
+        defender_moves,
+        seed: Some(seed),
+    };
+    (record, moves, weights)
+}
+
+/// A finished game: its index, outcome and what the output files need of it.
+struct FinishedGame {
+    index: u32,
+    record: GameRecord,
+    swapped: bool, // Whether the players swapped sides
+    encoded: Option<Vec<u8>>,                 // When archiving
+    exported: Option<(Vec<Move>, MoveWeights)>, // When exporting training data
+}
+
+/// Runs all games over `config.threads` workers, returning them in game order.
+fn simulate<G: Variant>(
+    config: &Config,
+    book: Option<&Arc<OpeningBook<G>>>,
+    wrap: &Wrap<G>,
+) -> (GameStats, Vec<FinishedGame>) {
+    let next_game = AtomicU32::new(0);
+
+    let results: Vec<(GameStats, Vec<FinishedGame>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut stats = GameStats::new(0);
+                    let mut records = Vec::new();
+                    loop {
+                        let index = next_game.fetch_add(1, Ordering::Relaxed);
+                        if index >= config.games {
+                            break;
+                        }
+                        let seed = rng::derive_seed(config.seed, index as u64);
+                        let (record, moves, weights) = play_game::<G>(config, book, wrap, seed, &mut stats);
+                        stats.record_game(&record);
+                        let encoded = config
+                            .archive
+                            .as_ref()
+                            .map(|_| encoding::encode_game(&G::new_game(), &moves).expect("Game too long to encode"));
+                        records.push(FinishedGame {
+                            index,
+                            record,
+                            swapped: sides_swapped(config, seed),
+                            encoded,
+                            exported: config.export.as_ref().map(|_| (moves, weights)),
+                        });
+                    }
+                    (stats, records)
+                })
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    });
+
+    let mut stats = GameStats::new(0);
+    let mut records = Vec::with_capacity(config.games as usize);
+    for (worker_stats, worker_records) in results {
+        stats.merge(worker_stats);
+        records.extend(worker_records);
+    }
+    records.sort_by_key(|game| game.index);
+    (stats, records)
+}
+
+fn winner_name(winner: CellType) -> String {
+    if winner == CellType::Empty { "Draw".to_string() } else { winner.to_string() }
+}
+
+fn write_archive(file_name: &str, games: &[Vec<u8>]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_name)?);
+    for game in games {
+        encoding::write_record(&mut file, game)?;
+    }
+    file.flush()
+}
+
+fn write_games(file_name: &str, config: &Config, games: &[FinishedGame]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(file_name)?);
+    writeln!(file, "game,winner,attacker_moves,defender_moves,seed,attacker,defender")?;
+    for (index, game) in games.iter().enumerate() {
+        let (record, (attacker, defender)) = (&game.record, game_players(config, game.swapped));
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{}",
+            index + 1,
+            winner_name(record.winner),
+            record.attacker_moves,
+            record.defender_moves,
+            record.seed.unwrap_or_default(),
+            attacker,
+            defender
+        )?;
+    }
+    file.flush()
+}
+
+/// Writes the positions of all games as training data (see `hnefatafl::dataset`).
+fn export<G: Variant>(dir: &str, chunk_size: usize, games: &[FinishedGame]) -> io::Result<DatasetSummary> {
+    let mut writer = DatasetWriter::<G>::new(dir, chunk_size)?;
+    for game in games {
+        if let Some((moves, weights)) = &game.exported {
+            writer.add_game(&G::new_game(), moves, weights, game.record.winner)?;
+        }
+    }
+    writer.finish()
+}
+
+/// Replays the game with the given seed, printing every move.
+fn replay<G: Variant>(config: &Config, book: Option<&Arc<OpeningBook<G>>>, wrap: &Wrap<G>, seed: u64) {
+    let (record, moves, _) = play_game::<G>(config, book, wrap, seed, &mut GameStats::new(0));
+    let (attacker, defender) = game_players(config, sides_swapped(config, seed));
+    println!("Replaying {} game with seed {} ({} vs {})", G::NAME, seed, attacker, defender);
+    for (index, mv) in moves.iter().enumerate() {
+        println!("{:>3}. {:?} -> {:?}", index + 1, mv.from, mv.to);
+    }
+    println!("Winner: {}", winner_name(record.winner));
+}
+
+/// Wins of each player over both sides, for runs where they swap sides.
+fn print_player_wins(config: &Config, games: &[FinishedGame]) {
+    // The winning side, seen from the `--attacker` player
+    let won_by_first = |game: &&FinishedGame| match game.record.winner {
+        CellType::Attacker => Some(!game.swapped),
+        CellType::Defender => Some(game.swapped),
+        _ => None,
+    };
+    let first_wins = games.iter().filter(|game| won_by_first(game) == Some(true)).count();
+    let second_wins = games.iter().filter(|game| won_by_first(game) == Some(false)).count();
+    let first_attacks = games.iter().filter(|game| !game.swapped).count();
+    println!(
+        "{} (--attacker) played the attackers in {} of {} games and won {}; {} (--defender) won {}",
+        config.attacker,
+        first_attacks,
+        games.len(),
+        first_wins,
+        config.defender,
+        second_wins
+    );
+}
+
+fn run<G: Variant>(config: &Config, wrap: &Wrap<G>) -> Result<(), String> {
+    let book = config.book.as_ref().map(OpeningBook::<G>::load).transpose()?.map(Arc::new);
+    if let Some(seed) = config.replay {
+        replay::<G>(config, book.as_ref(), wrap, seed);
+        return Ok(());
+    }
+
+    println!(
+        "Simulating {} {} games ({} vs {}, {} sides) on {} threads, seed {}",
+        config.games,
+        G::NAME,
+        config.attacker,
+        config.defender,
+        if config.random_sides { "random" } else { "fixed" },
+        config.threads,
+        config.seed
+    );
+    let start = Instant::now();
+    let (stats, mut games) = simulate::<G>(config, book.as_ref(), wrap);
+    let elapsed = start.elapsed();
+
+    println!(
+        "Finished {} games in {:.2?} ({:.0} games/s)",
+        stats.total_games,
+        elapsed,
+        stats.total_games as f64 / elapsed.as_secs_f64()
+    );
+    stats.print_summary();
+    if config.random_sides {
+        print_player_wins(config, &games);
+    }
+
+    let write_error = |e: io::Error| format!("Failed to write results: {}", e);
+    let games_file_name = format!("{}_games.csv", config.output);
+    let results_file_name = format!("{}_results.txt", config.output);
+    write_games(&games_file_name, config, &games).map_err(write_error)?;
+    stats.write_results(&results_file_name).map_err(write_error)?;
+    println!("Results written to {} and {}", games_file_name, results_file_name);
+    if let Some(archive) = &config.archive {
+        let encoded: Vec<Vec<u8>> = games.iter_mut().filter_map(|game| game.encoded.take()).collect();
+        write_archive(archive, &encoded).map_err(write_error)?;
+        println!("Games archived to {}", archive);
+    }
+    if let Some(dir) = &config.export {
+        let summary = export::<G>(dir, config.chunk_size, &games).map_err(write_error)?;
+        println!(
+            "Training data for {} positions of {} games written to {} ({} chunks)",
+            summary.samples, summary.games, dir, summary.chunks
+        );
+    }
+    Ok(())
+}
+
+/// Runs Brandubh games, with both players playing from the tablebase if there is one.
+fn run_brandubh(config: &Config) -> Result<(), String> {
+    let Some(path) = &config.tablebase else {
+        return run::<brandubh::GameState>(config, &|agent| agent);
+    };
+    let tablebase = Arc::new(Tablebase::load(path)?);
+    println!("Loaded tablebase {} (up to {} pieces)", path, tablebase.max_pieces());
+    run::<brandubh::GameState>(config, &move |agent| Box::new(TablebaseAgent::new(Arc::clone(&tablebase), agent)))
+}
+
+fn main() {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match config.variant.as_str() {
+        "brandubh" => run_brandubh(&config),
+        "copenhagen" if config.tablebase.is_some() => Err("The tablebase is for Brandubh only".to_string()),
+        "copenhagen" => run::<copenhagen::GameState>(&config, &|agent| agent),
+        other => {
+            eprintln!("Unknown variant: {}\n{}", other, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
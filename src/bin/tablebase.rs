@@ -0,0 +1,91 @@
+//! Generates the Brandubh endgame tablebase and writes it to disk.
+//!
+//! ```sh
+//! cargo run --release --bin tablebase -- --pieces 3 --output results/brandubh.tb
+//! ```
+//!
+//! `--info FILE` prints the statistics of an existing tablebase instead.
+
+use std::env;
+use std::process;
+use std::time::Instant;
+
+use hnefatafl::tablebase::{TableInfo, Tablebase, MAX_PIECES};
+
+const USAGE: &str = "Usage: tablebase [--pieces N] [--output FILE]
+       tablebase --info FILE";
+
+struct Config {
+    pieces: usize, // King included
+    output: String,
+    info: Option<String>,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        pieces: 3,
+        output: "results/brandubh.tb".to_string(),
+        info: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--pieces" => config.pieces = value.parse().map_err(|_| format!("Invalid piece count: {}", value))?,
+            "--output" => config.output = value,
+            "--info" => config.info = Some(value),
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    if !(1..=MAX_PIECES).contains(&config.pieces) {
+        return Err(format!("Piece count must be between 1 and {}", MAX_PIECES));
+    }
+    Ok(config)
+}
+
+fn print_table(info: &TableInfo) {
+    let line = format!(
+        "K+{}A+{}D: {:>9} positions, {:>9} wins, {:>9} losses, {:>9} draws, longest win {:>3} plies",
+        info.attackers, info.defenders, info.positions, info.wins, info.losses, info.draws, info.longest
+    );
+    // Tables loaded from a file have no generation time
+    if info.elapsed.is_zero() {
+        println!("{}", line);
+    } else {
+        println!("{} ({:.2?})", line, info.elapsed);
+    }
+}
+
+fn run(config: &Config) -> Result<(), String> {
+    if let Some(file) = &config.info {
+        let tablebase = Tablebase::load(file)?;
+        println!("{}: Brandubh tablebase up to {} pieces", file, tablebase.max_pieces());
+        tablebase.tables().iter().for_each(print_table);
+        return Ok(());
+    }
+
+    println!("Generating the Brandubh tablebase up to {} pieces (king included)", config.pieces);
+    let start = Instant::now();
+    let tablebase = Tablebase::generate_with_progress(config.pieces, print_table)?;
+    println!("Finished in {:.2?}", start.elapsed());
+    tablebase
+        .save(&config.output)
+        .map_err(|e| format!("Cannot write {}: {}", config.output, e))?;
+    println!("Tablebase written to {}", config.output);
+    Ok(())
+}
+
+fn main() {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(&config) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,286 @@
+//! Fits evaluation weights to the outcomes of self-play games (Texel tuning).
+//!
+//! Each round plays games between two searches using the current weights,
+//! collects every position with the game's result (1 attacker win, 0 defender
+//! win, 0.5 draw) and minimises the squared error between those results and
+//! `sigmoid(evaluation / scale)` by gradient descent. The scale is fitted once
+//! on the starting weights so the tuned weights stay in search score units.
+//!
+//! ```sh
+//! cargo run --release --bin tune -- --variant brandubh --games 400 --rounds 3
+//! ```
+//!
+//! The result is a weights file for `Weights::load` (and `simulate --weights`).
+
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use hnefatafl::agent::{Agent, Clock, RandomAgent};
+use hnefatafl::brandubh;
+use hnefatafl::cell::CellType;
+use hnefatafl::copenhagen;
+use hnefatafl::eval::{self, Feature, WeightedEvaluator, Weights, FEATURE_COUNT};
+use hnefatafl::rng;
+use hnefatafl::search::{SearchAgent, SearchLimits};
+use hnefatafl::variant::{opponent, Variant};
+
+const USAGE: &str = "Usage: tune [--variant brandubh|copenhagen] [--games N] [--depth N] [--random-plies N]
+            [--rounds N] [--iterations N] [--threads N] [--seed SEED]
+            [--weights FILE] [--output FILE]";
+
+struct Config {
+    variant: String,
+    games: u32,        // Games per round
+    depth: u32,        // Search depth of both players
+    random_plies: u32, // Random moves at the start of every game, for variety
+    rounds: u32,       // Rounds of playing games with the latest weights, then fitting
+    iterations: u32,   // Gradient descent steps per round
+    threads: usize,
+    seed: u64,
+    weights: Weights,  // Starting weights
+    output: String,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        variant: "brandubh".to_string(),
+        games: 200,
+        depth: 2,
+        random_plies: 6,
+        rounds: 1,
+        iterations: 2000,
+        threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        seed: rng::random_seed(),
+        weights: Weights::default(),
+        output: "results/weights.txt".to_string(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        let number = |name: &str| format!("Invalid {}: {}", name, value);
+        match flag.as_str() {
+            "--variant" => config.variant = value,
+            "--games" => config.games = value.parse().map_err(|_| number("game count"))?,
+            "--depth" => config.depth = value.parse().map_err(|_| number("depth"))?,
+            "--random-plies" => config.random_plies = value.parse().map_err(|_| number("ply count"))?,
+            "--rounds" => config.rounds = value.parse().map_err(|_| number("round count"))?,
+            "--iterations" => config.iterations = value.parse().map_err(|_| number("iteration count"))?,
+            "--threads" => config.threads = value.parse().map_err(|_| number("thread count"))?,
+            "--seed" => config.seed = value.parse().map_err(|_| number("seed"))?,
+            "--weights" => config.weights = Weights::load(&value)?,
+            "--output" => config.output = value,
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    if config.threads == 0 {
+        return Err("Thread count must be at least 1".to_string());
+    }
+    Ok(config)
+}
+
+/// A position's feature values and the result of its game for the attackers.
+type Sample = ([f64; FEATURE_COUNT], f64);
+
+/// Plays one game with `weights` on both sides and returns its positions,
+/// labelled with the final result.
+fn play_game<G: Variant>(config: &Config, weights: Weights, seed: u64) -> Vec<Sample> {
+    let mut opening = RandomAgent::new(seed);
+    let mut searcher = SearchAgent::new(WeightedEvaluator::new(weights), SearchLimits::depth(config.depth));
+    let clock = Clock::unlimited();
+    let mut game = G::new_game();
+    let mut positions = Vec::new();
+
+    let winner = loop {
+        if let Some(outcome) = game.outcome() {
+            break outcome;
+        }
+        if !game.has_legal_move() {
+            break opponent(game.side_to_move());
+        }
+        let mv = if game.move_count() < config.random_plies {
+            Agent::<G>::choose_move(&mut opening, &game, &clock)
+        } else {
+            positions.push(eval::features(&game).map(f64::from));
+            searcher.choose_move(&game, &clock)
+        };
+        if game.play(mv).is_err() {
+            break opponent(game.side_to_move());
+        }
+    };
+
+    let result = match winner {
+        CellType::Attacker => 1.0,
+        CellType::Empty => 0.5,
+        _ => 0.0,
+    };
+    positions.into_iter().map(|features| (features, result)).collect()
+}
+
+/// Plays `config.games` games over `config.threads` workers.
+fn collect_samples<G: Variant>(config: &Config, weights: Weights, round: u32) -> Vec<Sample> {
+    let next_game = AtomicU32::new(0);
+    let round_seed = rng::derive_seed(config.seed, round as u64);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut samples = Vec::new();
+                    loop {
+                        let index = next_game.fetch_add(1, Ordering::Relaxed);
+                        if index >= config.games {
+                            break;
+                        }
+                        samples.push((index, play_game::<G>(config, weights, rng::derive_seed(round_seed, index as u64))));
+                    }
+                    samples
+                })
+            })
+            .collect();
+        let mut games: Vec<_> = workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect();
+        // Game order, so the fit does not depend on thread scheduling
+        games.sort_by_key(|&(index, _)| index);
+        games.into_iter().flat_map(|(_, samples)| samples).collect()
+    })
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+fn mean_error(samples: &[Sample], weights: &[f64; FEATURE_COUNT], scale: f64) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|(features, result)| {
+            let score: f64 = features.iter().zip(weights).map(|(value, weight)| value * weight).sum();
+            (sigmoid(score / scale) - result).powi(2)
+        })
+        .sum();
+    total / samples.len() as f64
+}
+
+/// The scale mapping scores to expected results best for the given weights
+/// (golden-section search over a generous range).
+fn fit_scale(samples: &[Sample], weights: &[f64; FEATURE_COUNT]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (1.0f64, 10_000.0f64);
+    for _ in 0..60 {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if mean_error(samples, weights, a) < mean_error(samples, weights, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Minimises the mean squared error with Adam, which copes with features on
+/// very different scales (piece counts vs. mobility) without tuning a step
+/// size per feature.
+fn fit_weights(samples: &[Sample], weights: &mut [f64; FEATURE_COUNT], scale: f64, iterations: u32) {
+    const LEARNING_RATE: f64 = 1.0; // Score units per step
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    let mut moment = [0.0; FEATURE_COUNT];
+    let mut velocity = [0.0; FEATURE_COUNT];
+
+    for step in 1..=iterations {
+        let mut gradient = [0.0; FEATURE_COUNT];
+        for (features, result) in samples {
+            let score: f64 = features.iter().zip(weights.iter()).map(|(value, weight)| value * weight).sum();
+            let predicted = sigmoid(score / scale);
+            let common = 2.0 * (predicted - result) * predicted * (1.0 - predicted) / scale;
+            for (gradient, value) in gradient.iter_mut().zip(features) {
+                *gradient += common * value;
+            }
+        }
+        for i in 0..FEATURE_COUNT {
+            let g = gradient[i] / samples.len() as f64;
+            moment[i] = BETA1 * moment[i] + (1.0 - BETA1) * g;
+            velocity[i] = BETA2 * velocity[i] + (1.0 - BETA2) * g * g;
+            let corrected_moment = moment[i] / (1.0 - BETA1.powi(step as i32));
+            let corrected_velocity = velocity[i] / (1.0 - BETA2.powi(step as i32));
+            weights[i] -= LEARNING_RATE * corrected_moment / (corrected_velocity.sqrt() + 1e-12);
+        }
+    }
+}
+
+fn run<G: Variant>(config: &Config) -> Result<(), String> {
+    println!(
+        "Tuning {} weights: {} rounds of {} games at depth {} on {} threads, seed {}",
+        G::NAME, config.rounds, config.games, config.depth, config.threads, config.seed
+    );
+    let mut weights = config.weights.0.map(f64::from);
+    let mut samples = Vec::new();
+    let mut scale = None;
+    let mut error = 0.0;
+
+    for round in 0..config.rounds {
+        let start = Instant::now();
+        let current = Weights(weights.map(|weight| weight.round() as i32));
+        samples.extend(collect_samples::<G>(config, current, round));
+        if samples.is_empty() {
+            return Err("The games produced no positions to tune on".to_string());
+        }
+        let scale = *scale.get_or_insert_with(|| fit_scale(&samples, &weights));
+        let before = mean_error(&samples, &weights, scale);
+        fit_weights(&samples, &mut weights, scale, config.iterations);
+        error = mean_error(&samples, &weights, scale);
+        println!(
+            "Round {}: {} positions, scale {:.1}, error {:.5} -> {:.5} ({:.2?})",
+            round + 1,
+            samples.len(),
+            scale,
+            before,
+            error,
+            start.elapsed()
+        );
+    }
+
+    let tuned = Weights(weights.map(|weight| weight.round() as i32));
+    for feature in Feature::ALL {
+        println!("{:<22}{:>8} -> {:>6}", feature.name(), config.weights[feature], tuned[feature]);
+    }
+    let text = format!(
+        "# Tuned for {} on {} positions (seed {}, error {:.5})\n{}",
+        G::NAME,
+        samples.len(),
+        config.seed,
+        error,
+        tuned
+    );
+    fs::write(&config.output, text).map_err(|e| format!("Cannot write {}: {}", config.output, e))?;
+    println!("Weights written to {}", config.output);
+    Ok(())
+}
+
+fn main() {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match config.variant.as_str() {
+        "brandubh" => run::<brandubh::GameState>(&config),
+        "copenhagen" => run::<copenhagen::GameState>(&config),
+        other => {
+            eprintln!("Unknown variant: {}\n{}", other, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,371 @@
+//! Opening book built from recorded games, in the spirit of Polyglot books.
+//!
+//! Every position of the first plies of each game is reduced to its canonical
+//! form under the board symmetries, so mirrored and rotated openings share
+//! their statistics. An entry holds the canonical position's hash, a move in
+//! canonical coordinates, how often it was won, drawn and lost by the side
+//! that played it, and a weight (`2 * wins + draws`) for picking book moves.
+//! In a canonical position that is its own mirror image, such as the start
+//! position, equivalent moves share one entry, stored under the smallest of
+//! their images; probing gives all of them back.
+//!
+//! A book file has the 3-byte header of `encoding` (version, kind `b'B'`,
+//! board size), the number of entries as a little-endian `u32`, then the
+//! entries sorted by key, 18 bytes each, all little-endian: key `u64`, move
+//! `u16` (`from * 128 + to`, squares numbered `row * size + col`), weight,
+//! wins, draws and losses as `u16`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::agent::{Agent, Clock};
+use crate::cell::CellType;
+use crate::encoding::FORMAT_VERSION;
+use crate::rng::{self, GameRng};
+use crate::tt;
+use crate::variant::{opponent, Move, Variant};
+
+const KIND_BOOK: u8 = b'B';
+const ENTRY_SIZE: usize = 18;
+
+/// A book move as seen from the probed position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookMove {
+    pub mv: Move,
+    pub weight: u16,
+    pub wins: u16, // Results for the side to move after playing `mv`
+    pub draws: u16,
+    pub losses: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    key: u64,
+    mv: u16, // Canonical move, packed
+    weight: u16,
+    wins: u16,
+    draws: u16,
+    losses: u16,
+}
+
+/// Results of one move from one canonical position while building a book.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+/// Collects games into an `OpeningBook`.
+pub struct BookBuilder<G: Variant> {
+    max_plies: usize,
+    moves: HashMap<(u64, u16), Counts>,
+    games: usize,
+    _variant: std::marker::PhantomData<fn() -> G>,
+}
+
+impl<G: Variant> BookBuilder<G> {
+    /// A builder recording the first `max_plies` moves of every game.
+    pub fn new(max_plies: usize) -> Self {
+        BookBuilder {
+            max_plies,
+            moves: HashMap::new(),
+            games: 0,
+            _variant: std::marker::PhantomData,
+        }
+    }
+
+    /// Adds a finished game. Returns `Ok(false)` and adds nothing if the game
+    /// has no result yet; fails if a move is illegal.
+    pub fn add_game(&mut self, start: &G, moves: &[Move]) -> Result<bool, String> {
+        let mut game = start.clone();
+        let mut played = Vec::new();
+        for (ply, &mv) in moves.iter().enumerate() {
+            if ply < self.max_plies {
+                let (key, canonical_move) = book_move_key(&game, mv);
+                played.push((key, canonical_move, game.side_to_move()));
+            }
+            game.play(mv)?;
+        }
+
+        // A side without any move loses, as in the simulator
+        let winner = game
+            .outcome()
+            .or_else(|| (!game.has_legal_move()).then(|| opponent(game.side_to_move())));
+        let Some(winner) = winner else {
+            return Ok(false);
+        };
+        for (key, mv, side) in played {
+            let counts = self.moves.entry((key, mv)).or_default();
+            match winner {
+                CellType::Empty => counts.draws += 1,
+                winner if G::belongs_to(winner, side) => counts.wins += 1,
+                _ => counts.losses += 1,
+            }
+        }
+        self.games += 1;
+        Ok(true)
+    }
+
+    /// Games added so far.
+    pub fn games(&self) -> usize {
+        self.games
+    }
+
+    /// The book, keeping only moves played in at least `min_games` games.
+    pub fn build(&self, min_games: u32) -> OpeningBook<G> {
+        let saturate = |count: u32| count.min(u16::MAX as u32) as u16;
+        let mut entries: Vec<Entry> = self
+            .moves
+            .iter()
+            .filter(|(_, counts)| counts.wins + counts.draws + counts.losses >= min_games)
+            .map(|(&(key, mv), counts)| Entry {
+                key,
+                mv,
+                weight: saturate(2 * counts.wins + counts.draws),
+                wins: saturate(counts.wins),
+                draws: saturate(counts.draws),
+                losses: saturate(counts.losses),
+            })
+            .collect();
+        entries.sort_by_key(|entry| (entry.key, std::cmp::Reverse(entry.weight), entry.mv));
+        OpeningBook {
+            entries,
+            _variant: std::marker::PhantomData,
+        }
+    }
+}
+
+pub struct OpeningBook<G: Variant> {
+    entries: Vec<Entry>, // Sorted by key
+    _variant: std::marker::PhantomData<fn() -> G>,
+}
+
+impl<G: Variant> OpeningBook<G> {
+    /// Number of (position, move) entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of distinct positions in the book.
+    pub fn positions(&self) -> usize {
+        let mut positions = 0;
+        let mut previous = None;
+        for entry in &self.entries {
+            if previous != Some(entry.key) {
+                positions += 1;
+                previous = Some(entry.key);
+            }
+        }
+        positions
+    }
+
+    /// The book moves for `position`, heaviest first, with every move of an
+    /// entry that stands for several equivalent moves. Moves that are not
+    /// legal in the position (after a hash collision) are left out.
+    pub fn probe(&self, position: &G) -> Vec<BookMove> {
+        self.lookup(position)
+            .into_iter()
+            .flat_map(|(entry, moves)| {
+                moves.into_iter().map(|mv| BookMove {
+                    mv,
+                    weight: entry.weight,
+                    wins: entry.wins,
+                    draws: entry.draws,
+                    losses: entry.losses,
+                })
+            })
+            .collect()
+    }
+
+    /// A book move picked at random in proportion to its entry's weight, or
+    /// `None` if the position is not in the book (or none of its moves has any
+    /// weight). Equivalent moves of one entry are equally likely.
+    pub fn choose(&self, position: &G, rng: &mut GameRng) -> Option<Move> {
+        let entries = self.lookup(position);
+        let total: u32 = entries.iter().map(|(entry, _)| entry.weight as u32).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = rng.random_range(0..total);
+        for (entry, moves) in entries {
+            if pick < entry.weight as u32 {
+                return Some(moves[rng.random_range(0..moves.len())]);
+            }
+            pick -= entry.weight as u32;
+        }
+        None
+    }
+
+    /// The entries for `position`, each with the legal moves it stands for in
+    /// the position's own coordinates; entries without any are left out.
+    fn lookup(&self, position: &G) -> Vec<(&Entry, Vec<Move>)> {
+        let (canonical, symmetry) = position.canonical_form();
+        let self_symmetries = canonical.self_symmetries();
+        let key = tt::position_hash(&canonical);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let legal_moves = position.legal_moves();
+        self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| {
+                let canonical_move = unpack_move::<G>(entry.mv);
+                let mut moves = Vec::new();
+                for self_symmetry in &self_symmetries {
+                    let mv = symmetry.inverse().apply_move(self_symmetry.apply_move(canonical_move, G::SIZE), G::SIZE);
+                    if legal_moves.contains(&mv) && !moves.contains(&mv) {
+                        moves.push(mv);
+                    }
+                }
+                (!moves.is_empty()).then_some((entry, moves))
+            })
+            .collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![FORMAT_VERSION, KIND_BOOK, G::SIZE as u8];
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.key.to_le_bytes());
+            for value in [entry.mv, entry.weight, entry.wins, entry.draws, entry.losses] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 7 {
+            return Err("Unexpected end of data".to_string());
+        }
+        if bytes[0] != FORMAT_VERSION {
+            return Err(format!("Unsupported format version {} (expected {})", bytes[0], FORMAT_VERSION));
+        }
+        if bytes[1] != KIND_BOOK {
+            return Err(format!("Expected record kind '{}', found '{}'", KIND_BOOK as char, bytes[1] as char));
+        }
+        if bytes[2] as usize != G::SIZE {
+            return Err(format!("Board size {} does not match {} ({})", bytes[2], G::NAME, G::SIZE));
+        }
+        let count = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]) as usize;
+        let body = &bytes[7..];
+        if body.len() != count * ENTRY_SIZE {
+            return Err(format!("Expected {} entries, found {} bytes of them", count, body.len()));
+        }
+
+        let entries: Vec<Entry> = body
+            .chunks_exact(ENTRY_SIZE)
+            .map(|chunk| {
+                let u16_at = |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+                Entry {
+                    key: u64::from_le_bytes(chunk[..8].try_into().unwrap()),
+                    mv: u16_at(8),
+                    weight: u16_at(10),
+                    wins: u16_at(12),
+                    draws: u16_at(14),
+                    losses: u16_at(16),
+                }
+            })
+            .collect();
+        if !entries.is_sorted_by_key(|entry| entry.key) {
+            return Err("Book entries are not sorted by key".to_string());
+        }
+        if let Some(entry) = entries.iter().find(|entry| !is_valid_move::<G>(entry.mv)) {
+            return Err(format!("Invalid move {:#06x} in book", entry.mv));
+        }
+        Ok(OpeningBook {
+            entries,
+            _variant: std::marker::PhantomData,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// The entry key of `mv` in `position`: the hash of the canonical form of
+/// the position, shared by all its symmetric images, and the packed
+/// canonical move, shared by all equivalent moves. Packing keeps the order of
+/// squares, so the smallest image `canonical_move` picks is also the smallest
+/// packed one.
+fn book_move_key<G: Variant>(position: &G, mv: Move) -> (u64, u16) {
+    let (canonical, _) = position.canonical_form();
+    (tt::position_hash(&canonical), pack_move::<G>(position.canonical_move(mv)))
+}
+
+fn pack_move<G: Variant>(mv: Move) -> u16 {
+    let square = |(row, col): (usize, usize)| (row * G::SIZE + col) as u16;
+    square(mv.from) * 128 + square(mv.to)
+}
+
+fn unpack_move<G: Variant>(packed: u16) -> Move {
+    let square = |index: u16| (index as usize / G::SIZE, index as usize % G::SIZE);
+    Move {
+        from: square(packed / 128),
+        to: square(packed % 128),
+    }
+}
+
+fn is_valid_move<G: Variant>(packed: u16) -> bool {
+    let squares = (G::SIZE * G::SIZE) as u16;
+    packed / 128 < squares && packed % 128 < squares
+}
+
+/// Plays a weighted book move while the position is in the book and leaves
+/// the rest of the game to `fallback`.
+pub struct BookAgent<G: Variant, A: Agent<G>> {
+    book: Arc<OpeningBook<G>>,
+    fallback: A,
+    rng: GameRng,
+    in_book: bool, // Whether the last move came from the book
+}
+
+impl<G: Variant, A: Agent<G>> BookAgent<G, A> {
+    /// `seed` drives the choice between book moves.
+    pub fn new(book: Arc<OpeningBook<G>>, fallback: A, seed: u64) -> Self {
+        BookAgent {
+            book,
+            fallback,
+            rng: rng::rng_from_seed(seed),
+            in_book: false,
+        }
+    }
+}
+
+impl<G: Variant, A: Agent<G>> Agent<G> for BookAgent<G, A> {
+    fn name(&self) -> String {
+        format!("{}+book", self.fallback.name())
+    }
+
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move {
+        let book_move = self.book.choose(position, &mut self.rng);
+        self.in_book = book_move.is_some();
+        book_move.unwrap_or_else(|| self.fallback.choose_move(position, clock))
+    }
+
+    fn try_choose_move(&mut self, position: &G, clock: &Clock) -> Result<Move, String> {
+        let book_move = self.book.choose(position, &mut self.rng);
+        self.in_book = book_move.is_some();
+        book_move.map_or_else(|| self.fallback.try_choose_move(position, clock), Ok)
+    }
+
+    fn move_weights(&self) -> Option<Vec<(Move, u32)>> {
+        if self.in_book { None } else { self.fallback.move_weights() }
+    }
+}
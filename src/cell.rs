@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum CellType {
+    Empty,
+    Attacker,
+    Defender,
+    King,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Cell {
+    pub cell_type: CellType,
+    pub is_corner: bool,
+    pub is_throne: bool,
+    pub is_selected: bool,
+    pub is_possible_move: bool,
+}
+
+impl fmt::Display for CellType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellType::Empty => write!(f, "Empty"),
+            CellType::Attacker => write!(f, "Attacker"),
+            CellType::Defender => write!(f, "Defender"),
+            CellType::King => write!(f, "King"),
+        }
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Build the string for cell type and additional information (Corner and/or Throne)
+        let mut display_str = self.cell_type.to_string(); // Get the cell's type as string
+
+        // Append Corner or Throne information
+        if self.is_corner {
+            display_str.push_str(" (Corner)");
+        }
+        if self.is_throne {
+            display_str.push_str(" (Throne)");
+        }
+        if self.is_selected {
+            display_str.push_str(" (Selected)");
+        }
+
+        // Write the final string to the formatter
+        write!(f, "{}", display_str)
+    }
+}
@@ -1,57 +1,7 @@
-#[warn(unused_variables)]
-
 use serde::{Deserialize, Serialize};
-use std::fmt;
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub enum CellType {
-    Empty,
-    Attacker,
-    Defender,
-    King,
-}
-
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub struct Cell {
-    pub cell_type: CellType,
-    pub is_corner: bool,
-    pub is_throne: bool,
-    pub is_selected: bool,
-    pub is_possible_move: bool,
-}
 
-
-impl fmt::Display for CellType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            CellType::Empty => write!(f, "Empty"),
-            CellType::Attacker => write!(f, "Attacker"),
-            CellType::Defender => write!(f, "Defender"),
-            CellType::King => write!(f, "King"),
-        }
-    }
-}
-
-impl fmt::Display for Cell {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Build the string for cell type and additional information (Corner and/or Throne)
-        let mut display_str = self.cell_type.to_string(); // Get the cell's type as string
-
-        // Append Corner or Throne information
-        if self.is_corner {
-            display_str.push_str(" (Corner)");
-        }
-        if self.is_throne {
-            display_str.push_str(" (Throne)");
-        }
-        if self.is_selected {
-            display_str.push_str(" (Selected)");
-        }
-
-        // Write the final string to the formatter
-        write!(f, "{}", display_str)
-    }
-}
+pub use crate::cell::{Cell, CellType};
+use crate::variant::{Move, Variant};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
@@ -162,7 +112,7 @@ impl GameState {
             board_message: "Current turn: Attacker".to_string(),
             game_title: "Copenhagen".to_string(),
             last_click: (0, 0),
-            id: id,
+            id,
             move_done: false,
             attacker_moves: 0,
             defender_moves: 0,
@@ -377,7 +327,7 @@ impl GameState {
         }
     
         // Make the move
-        let mut moved_piece = self.board[from.0][from.1].clone();
+        let mut moved_piece = self.board[from.0][from.1];
         if moved_piece.is_throne {
             moved_piece.is_throne = false;
         }
@@ -468,7 +418,7 @@ impl GameState {
             (pos.0, pos.1 + 1), // Right
         ];
     
-        let cell = self.board[pos.0][pos.1].clone(); // Clone the current cell (with cell_type and is_corner)
+        let cell = self.board[pos.0][pos.1]; // Clone the current cell (with cell_type and is_corner)
     
         for (i, &(nx, ny)) in neighbors.iter().enumerate() {
             if self.is_within_bounds((nx, ny)) {
@@ -709,4 +659,73 @@ impl GameState {
     
         None
     }
-}
\ No newline at end of file
+}
+
+impl Variant for GameState {
+    const NAME: &'static str = "Copenhagen";
+    const SIZE: usize = 11;
+    const HOSTILE_THRONE: bool = true;
+
+    fn new_game() -> Self {
+        GameState::new(1)
+    }
+
+    fn cell(&self, pos: (usize, usize)) -> Cell {
+        self.board[pos.0][pos.1]
+    }
+
+    fn set_cell(&mut self, pos: (usize, usize), cell: Cell) {
+        self.board[pos.0][pos.1] = cell;
+    }
+
+    fn side_to_move(&self) -> CellType {
+        self.current_turn.cell_type
+    }
+
+    fn set_side_to_move(&mut self, side: CellType) {
+        self.current_turn.cell_type = side;
+        self.board_message = format!("Current turn: {}", side);
+    }
+
+    fn valid_moves_from(&self, pos: (usize, usize)) -> Vec<(usize, usize)> {
+        self.calculate_valid_moves(pos)
+    }
+
+    fn apply_move(&mut self, mv: Move) -> Result<(), String> {
+        let side = self.current_turn.cell_type;
+        self.make_move(mv.from, mv.to)?;
+        match side {
+            CellType::Attacker => self.attacker_moves += 1,
+            _ => self.defender_moves += 1,
+        }
+        Ok(())
+    }
+
+    fn winner(&self) -> Option<CellType> {
+        self.winner.map(|cell| cell.cell_type)
+    }
+
+    fn set_winner(&mut self, winner: Option<CellType>) {
+        self.winner = winner.map(|cell_type| Cell {
+            cell_type,
+            is_corner: false,
+            is_throne: false,
+            is_selected: false,
+            is_possible_move: false,
+        });
+        self.game_over = winner.is_some();
+    }
+
+    fn move_count(&self) -> u32 {
+        self.attacker_moves + self.defender_moves
+    }
+
+    fn side_move_counts(&self) -> (u32, u32) {
+        (self.attacker_moves, self.defender_moves)
+    }
+
+    fn set_side_move_counts(&mut self, attacker_moves: u32, defender_moves: u32) {
+        self.attacker_moves = attacker_moves;
+        self.defender_moves = defender_moves;
+    }
+}
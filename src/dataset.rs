@@ -0,0 +1,297 @@
+//! Training data for policy/value networks, written as NumPy `.npy` files.
+//!
+//! Every position of a game (before each move) becomes one sample. Samples are
+//! written in chunks of up to `chunk_size` samples; chunk `N` of a dataset in
+//! `DIR` is a set of files `DIR/chunk_NNNNN_<array>.npy`, all with the sample
+//! count `n` as their first dimension (`S` is the board size, `P` the policy
+//! size below):
+//!
+//! - `boards`: `uint8 [n, 3, S, S]`, one plane each for attackers, defenders
+//!   and the king, 1 where the piece stands.
+//! - `side`: `uint8 [n]`, the side to move, 0 attacker and 1 defender.
+//! - `legal`: `uint8 [n, P]`, 1 for the legal moves of the side to move.
+//! - `policy`: `float32 [n, P]`, the move distribution to learn: the agent's
+//!   move weights (MCTS root visits) scaled to sum to 1, or 1 for the move
+//!   played if the agent has none.
+//! - `played`: `int32 [n]`, the policy index of the move played.
+//! - `outcome`: `float32 [n]`, the game's result for the side to move:
+//!   1 win, 0 draw, -1 loss.
+//!
+//! A move's policy index is `(from * 4 + direction) * (S - 1) + distance - 1`,
+//! where `from` is the square `row * S + col` the piece leaves, `direction` is
+//! 0 up (decreasing row), 1 down, 2 left (decreasing column), 3 right, and
+//! `distance` the number of squares moved, so `P = 4 * S * S * (S - 1)`:
+//! 1176 for Brandubh, 4840 for Copenhagen. The arrays load with `numpy.load`.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cell::CellType;
+use crate::variant::{Move, Variant};
+
+/// Samples per chunk if not set otherwise.
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+const PLANES: [CellType; 3] = [CellType::Attacker, CellType::Defender, CellType::King];
+
+/// Number of entries of a policy vector for `G`.
+pub fn policy_size<G: Variant>() -> usize {
+    4 * G::SIZE * G::SIZE * (G::SIZE - 1)
+}
+
+/// Policy index of `mv`, or `None` if it is not a rook move on the board.
+pub fn move_index<G: Variant>(mv: Move) -> Option<usize> {
+    let ((from_row, from_col), (to_row, to_col)) = (mv.from, mv.to);
+    let (direction, distance) = if from_col == to_col && to_row < from_row {
+        (0, from_row - to_row)
+    } else if from_col == to_col && to_row > from_row {
+        (1, to_row - from_row)
+    } else if from_row == to_row && to_col < from_col {
+        (2, from_col - to_col)
+    } else if from_row == to_row && to_col > from_col {
+        (3, to_col - from_col)
+    } else {
+        return None;
+    };
+    if from_row >= G::SIZE || from_col >= G::SIZE || to_row >= G::SIZE || to_col >= G::SIZE {
+        return None;
+    }
+    let from = from_row * G::SIZE + from_col;
+    Some((from * 4 + direction) * (G::SIZE - 1) + distance - 1)
+}
+
+/// The move with policy index `index`, which may leave the board.
+pub fn index_move<G: Variant>(index: usize) -> Move {
+    let distance = index % (G::SIZE - 1) + 1;
+    let direction = index / (G::SIZE - 1) % 4;
+    let from = index / (G::SIZE - 1) / 4;
+    let (row, col) = (from / G::SIZE, from % G::SIZE);
+    let to = match direction {
+        0 => (row.wrapping_sub(distance), col),
+        1 => (row + distance, col),
+        2 => (row, col.wrapping_sub(distance)),
+        _ => (row, col + distance),
+    };
+    Move { from: (row, col), to }
+}
+
+/// Totals of a finished dataset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatasetSummary {
+    pub games: usize,
+    pub samples: usize,
+    pub chunks: usize,
+}
+
+/// The arrays of a run of samples, one entry (or row) per sample.
+#[derive(Default)]
+struct Samples {
+    boards: Vec<u8>,
+    sides: Vec<u8>,
+    legal: Vec<u8>,
+    policy: Vec<f32>,
+    played: Vec<i32>,
+    outcomes: Vec<f32>,
+}
+
+impl Samples {
+    fn len(&self) -> usize {
+        self.sides.len()
+    }
+
+    /// Appends sample `index` of `other`, whose rows are the sizes for `G`.
+    fn push_from<G: Variant>(&mut self, other: &Samples, index: usize) {
+        let board = PLANES.len() * G::SIZE * G::SIZE;
+        let policy = policy_size::<G>();
+        self.boards.extend_from_slice(&other.boards[index * board..(index + 1) * board]);
+        self.sides.push(other.sides[index]);
+        self.legal.extend_from_slice(&other.legal[index * policy..(index + 1) * policy]);
+        self.policy.extend_from_slice(&other.policy[index * policy..(index + 1) * policy]);
+        self.played.push(other.played[index]);
+        self.outcomes.push(other.outcomes[index]);
+    }
+
+    fn clear(&mut self) {
+        self.boards.clear();
+        self.sides.clear();
+        self.legal.clear();
+        self.policy.clear();
+        self.played.clear();
+        self.outcomes.clear();
+    }
+}
+
+/// Collects samples from games and writes them out chunk by chunk.
+pub struct DatasetWriter<G: Variant> {
+    dir: PathBuf,
+    chunk_size: usize,
+    samples: Samples,
+    summary: DatasetSummary,
+    _variant: std::marker::PhantomData<fn() -> G>,
+}
+
+impl<G: Variant> DatasetWriter<G> {
+    /// Writes chunks of up to `chunk_size` samples into `dir`, which is created if needed.
+    pub fn new(dir: impl AsRef<Path>, chunk_size: usize) -> io::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(DatasetWriter {
+            dir: dir.as_ref().to_path_buf(),
+            chunk_size: chunk_size.max(1),
+            samples: Samples::default(),
+            summary: DatasetSummary::default(),
+            _variant: std::marker::PhantomData,
+        })
+    }
+
+    /// Adds every position of a game played from `start`. `weights[i]` are the
+    /// move weights of the agent that played `moves[i]`, if it had any;
+    /// `winner` is the game's result (`CellType::Empty` for a draw). Fails if
+    /// a move is illegal, in which case none of the game is added, or if a
+    /// chunk cannot be written.
+    pub fn add_game(
+        &mut self,
+        start: &G,
+        moves: &[Move],
+        weights: &[Option<Vec<(Move, u32)>>],
+        winner: CellType,
+    ) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut position = start.clone();
+        let mut game = Samples::default();
+        for (ply, &mv) in moves.iter().enumerate() {
+            let played = move_index::<G>(mv).ok_or_else(|| invalid(format!("Move {} is not a rook move", ply + 1)))?;
+            let side = position.side_to_move();
+
+            for piece in PLANES {
+                for row in 0..G::SIZE {
+                    for col in 0..G::SIZE {
+                        game.boards.push(u8::from(position.cell((row, col)).cell_type == piece));
+                    }
+                }
+            }
+            game.sides.push(u8::from(side != CellType::Attacker));
+
+            let mut legal = vec![0; policy_size::<G>()];
+            for legal_move in position.legal_moves() {
+                if let Some(index) = move_index::<G>(legal_move) {
+                    legal[index] = 1;
+                }
+            }
+            game.legal.extend_from_slice(&legal);
+
+            let mut policy = vec![0.0; policy_size::<G>()];
+            let total: u64 = weights
+                .get(ply)
+                .and_then(Option::as_ref)
+                .map_or(0, |weights| weights.iter().map(|&(_, weight)| weight as u64).sum());
+            if total > 0 {
+                for &(weighted, weight) in weights[ply].as_ref().unwrap() {
+                    if let Some(index) = move_index::<G>(weighted) {
+                        policy[index] = (weight as f64 / total as f64) as f32;
+                    }
+                }
+            } else {
+                policy[played] = 1.0;
+            }
+            game.policy.extend_from_slice(&policy);
+            game.played.push(played as i32);
+
+            game.outcomes.push(match winner {
+                CellType::Empty => 0.0,
+                winner if G::belongs_to(winner, side) => 1.0,
+                _ => -1.0,
+            });
+
+            position
+                .play(mv)
+                .map_err(|err| invalid(format!("Move {} is illegal: {}", ply + 1, err)))?;
+        }
+
+        for index in 0..game.len() {
+            self.samples.push_from::<G>(&game, index);
+            self.summary.samples += 1;
+            if self.samples.len() == self.chunk_size {
+                self.flush()?;
+            }
+        }
+        self.summary.games += 1;
+        Ok(())
+    }
+
+    /// Writes the last, partial chunk.
+    pub fn finish(mut self) -> io::Result<DatasetSummary> {
+        if self.samples.len() > 0 {
+            self.flush()?;
+        }
+        Ok(self.summary)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let samples = &self.samples;
+        let count = samples.len();
+        let (size, policy_size) = (G::SIZE, policy_size::<G>());
+        let chunk = self.summary.chunks;
+        let path = |name: &str| self.dir.join(format!("chunk_{:05}_{}.npy", chunk, name));
+
+        write_npy(&path("boards"), "|u1", &[count, PLANES.len(), size, size], &samples.boards)?;
+        write_npy(&path("side"), "|u1", &[count], &samples.sides)?;
+        write_npy(&path("legal"), "|u1", &[count, policy_size], &samples.legal)?;
+        let policy: Vec<u8> = samples.policy.iter().flat_map(|value| value.to_le_bytes()).collect();
+        write_npy(&path("policy"), "<f4", &[count, policy_size], &policy)?;
+        let played: Vec<u8> = samples.played.iter().flat_map(|value| value.to_le_bytes()).collect();
+        write_npy(&path("played"), "<i4", &[count], &played)?;
+        let outcomes: Vec<u8> = samples.outcomes.iter().flat_map(|value| value.to_le_bytes()).collect();
+        write_npy(&path("outcome"), "<f4", &[count], &outcomes)?;
+
+        self.samples.clear();
+        self.summary.chunks += 1;
+        Ok(())
+    }
+}
+
+/// Writes a C-ordered array in NPY format version 1.0: the magic string, the
+/// version, the header length as a little-endian `u16`, then a Python dict
+/// literal padded with spaces and ending in a newline, so that the data starts
+/// at a multiple of 64 bytes.
+fn write_npy(path: &Path, descr: &str, shape: &[usize], data: &[u8]) -> io::Result<()> {
+    let dims: Vec<String> = shape.iter().map(usize::to_string).collect();
+    // A one-dimensional shape needs a trailing comma to be a Python tuple
+    let shape = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    let unpadded = 6 + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(data)?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brandubh::GameState as Brandubh;
+    use crate::test_util::random_moves;
+
+    #[test]
+    fn games_with_an_illegal_move_are_left_out() {
+        let dir = std::env::temp_dir().join(format!("hnefatafl_dataset_test_{}", std::process::id()));
+        let mut writer = DatasetWriter::<Brandubh>::new(&dir, 4).unwrap();
+        let start = Brandubh::new_game();
+
+        let good = random_moves::<Brandubh>(6, 3);
+        writer.add_game(&start, &good, &[], CellType::Empty).unwrap();
+        // Long enough to fill a chunk before the bad move is reached
+        let mut bad = random_moves::<Brandubh>(6, 5);
+        bad.push(Move { from: (0, 0), to: (0, 1) });
+        assert!(writer.add_game(&start, &bad, &[], CellType::Attacker).is_err());
+        let summary = writer.finish().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(summary, DatasetSummary { games: 1, samples: 6, chunks: 2 });
+    }
+}
@@ -0,0 +1,489 @@
+//! Compact binary encoding of positions and games.
+//!
+//! Every encoding starts with a 3-byte header: format version, kind
+//! (`b'P'` position, `b'G'` game) and board size.
+//!
+//! A position body is one bitset per piece set (attackers, defenders, king),
+//! each `ceil(size * size / 8)` bytes with square `row * size + col` in bit
+//! `index % 8` of byte `index / 8`, followed by a status byte (bit 0: side to
+//! move, 0 attacker / 1 defender; bits 1-2: 0 ongoing, 1 attacker won,
+//! 2 defender won, 3 draw) and the attacker and defender move counters as
+//! little-endian `u16`. A Brandubh position takes 29 bytes, Copenhagen 56.
+//!
+//! A game body is the start position body, the number of moves as a
+//! little-endian `u16`, then each move as two square indices (from, to).
+//!
+//! Archives are sequences of records, each an encoded game or position
+//! prefixed by its length as a little-endian `u32`.
+//!
+//! Positions also have a one-line text notation for command lines and
+//! protocols: the rows from row 0 down, separated by `/`, with `.` for an
+//! empty square and `A`, `D`, `K` for attackers, defenders and the king, then
+//! a space and the side to move, `a` or `d`. The Brandubh start position is
+//! `...A.../...A.../...D.../AADKDAA/...D.../...A.../...A... a`.
+//!
+//! In text, square `(row, col)` is the column letter (`a` for column 0)
+//! followed by the row number counted from 1, so `(0, 3)` is `d1`, and a
+//! move is its two squares joined by `-`, as in `d1-b1`.
+
+use std::io::{self, Read, Write};
+
+use crate::cell::{Cell, CellType};
+use crate::variant::{Move, Variant};
+
+pub const FORMAT_VERSION: u8 = 1;
+
+const KIND_POSITION: u8 = b'P';
+const KIND_GAME: u8 = b'G';
+
+const PIECE_SETS: [CellType; 3] = [CellType::Attacker, CellType::Defender, CellType::King];
+
+pub fn encode_position<G: Variant>(game: &G) -> Vec<u8> {
+    let mut bytes = header::<G>(KIND_POSITION);
+    write_position(game, &mut bytes);
+    bytes
+}
+
+pub fn decode_position<G: Variant>(bytes: &[u8]) -> Result<G, String> {
+    let mut reader = ByteReader::new(bytes);
+    read_header::<G>(&mut reader, KIND_POSITION)?;
+    let game = read_position(&mut reader)?;
+    reader.finish()?;
+    Ok(game)
+}
+
+/// Encodes a game as its start position and the moves played from it.
+pub fn encode_game<G: Variant>(start: &G, moves: &[Move]) -> Result<Vec<u8>, String> {
+    if moves.len() > u16::MAX as usize {
+        return Err(format!("Too many moves to encode: {}", moves.len()));
+    }
+    let mut bytes = header::<G>(KIND_GAME);
+    write_position(start, &mut bytes);
+    bytes.extend_from_slice(&(moves.len() as u16).to_le_bytes());
+    for mv in moves {
+        bytes.push(square_index::<G>(mv.from)?);
+        bytes.push(square_index::<G>(mv.to)?);
+    }
+    Ok(bytes)
+}
+
+/// Decodes a game, checking every move against the rules by replaying it.
+/// Returns the start position and the moves.
+pub fn decode_game<G: Variant>(bytes: &[u8]) -> Result<(G, Vec<Move>), String> {
+    let mut reader = ByteReader::new(bytes);
+    read_header::<G>(&mut reader, KIND_GAME)?;
+    let start: G = read_position(&mut reader)?;
+    let count = reader.u16()? as usize;
+
+    let mut game = start.clone();
+    let mut moves = Vec::with_capacity(count);
+    for index in 0..count {
+        let mv = Move {
+            from: square::<G>(reader.u8()?)?,
+            to: square::<G>(reader.u8()?)?,
+        };
+        game.play(mv)
+            .map_err(|err| format!("Move {} ({:?} -> {:?}) is illegal: {}", index + 1, mv.from, mv.to, err))?;
+        moves.push(mv);
+    }
+    reader.finish()?;
+    Ok((start, moves))
+}
+
+/// Appends one length-prefixed record to an archive.
+pub fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+    writer.write_all(&(record.len() as u32).to_le_bytes())?;
+    writer.write_all(record)
+}
+
+/// Reads the next record of an archive, or `None` at the end of it.
+pub fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut record = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut record)?;
+    Ok(Some(record))
+}
+
+/// The text notation of a position. Move counters and results are not part of it.
+pub fn position_to_text<G: Variant>(game: &G) -> String {
+    let rows: Vec<String> = (0..G::SIZE)
+        .map(|row| {
+            (0..G::SIZE)
+                .map(|col| match game.cell((row, col)).cell_type {
+                    CellType::Attacker => 'A',
+                    CellType::Defender => 'D',
+                    CellType::King => 'K',
+                    CellType::Empty => '.',
+                })
+                .collect()
+        })
+        .collect();
+    let side = if game.side_to_move() == CellType::Attacker { 'a' } else { 'd' };
+    format!("{} {}", rows.join("/"), side)
+}
+
+/// Parses the text notation of a position, with both move counters at zero.
+pub fn position_from_text<G: Variant>(text: &str) -> Result<G, String> {
+    let mut parts = text.split_whitespace();
+    let board = parts.next().ok_or("Empty position")?;
+    let side = match parts.next() {
+        Some("a") => CellType::Attacker,
+        Some("d") => CellType::Defender,
+        Some(other) => return Err(format!("Invalid side to move: {} (expected a or d)", other)),
+        None => return Err("Missing side to move".to_string()),
+    };
+    if parts.next().is_some() {
+        return Err("Unexpected text after the side to move".to_string());
+    }
+
+    let rows: Vec<&str> = board.split('/').collect();
+    if rows.len() != G::SIZE {
+        return Err(format!("Expected {} rows for {}, found {}", G::SIZE, G::NAME, rows.len()));
+    }
+    let mut game = G::empty_board();
+    let mut kings = 0;
+    for (row, line) in rows.iter().enumerate() {
+        if line.chars().count() != G::SIZE {
+            return Err(format!("Row {} has {} squares, expected {}", row, line.chars().count(), G::SIZE));
+        }
+        for (col, symbol) in line.chars().enumerate() {
+            let cell_type = match symbol {
+                'A' => CellType::Attacker,
+                'D' => CellType::Defender,
+                'K' => CellType::King,
+                '.' => CellType::Empty,
+                other => return Err(format!("Invalid square '{}' in row {}", other, row)),
+            };
+            if cell_type == CellType::King {
+                kings += 1;
+            }
+            let cell = game.cell((row, col));
+            game.set_cell((row, col), Cell { cell_type, ..cell });
+        }
+    }
+    if kings > 1 {
+        return Err(format!("Position has {} kings", kings));
+    }
+    game.set_side_to_move(side);
+    game.set_side_move_counts(0, 0);
+    Ok(game)
+}
+
+/// The text notation of a move, e.g. `d1-b1`.
+pub fn move_to_text(mv: Move) -> String {
+    let square = |(row, col): (usize, usize)| format!("{}{}", (b'a' + col as u8) as char, row + 1);
+    format!("{}-{}", square(mv.from), square(mv.to))
+}
+
+/// Parses the text notation of a move on the board of `G`. The move is not
+/// checked against the rules.
+pub fn move_from_text<G: Variant>(text: &str) -> Result<Move, String> {
+    let square = |text: &str| -> Result<(usize, usize), String> {
+        let mut chars = text.chars();
+        let col = chars
+            .next()
+            .filter(char::is_ascii_lowercase)
+            .map(|letter| letter as usize - 'a' as usize);
+        let row = chars.as_str().parse::<usize>().ok().filter(|&row| row >= 1).map(|row| row - 1);
+        match (row, col) {
+            (Some(row), Some(col)) if row < G::SIZE && col < G::SIZE => Ok((row, col)),
+            _ => Err(format!("Invalid square: {}", text)),
+        }
+    };
+    let (from, to) = text.split_once('-').ok_or(format!("Invalid move: {}", text))?;
+    Ok(Move {
+        from: square(from)?,
+        to: square(to)?,
+    })
+}
+
+fn header<G: Variant>(kind: u8) -> Vec<u8> {
+    vec![FORMAT_VERSION, kind, G::SIZE as u8]
+}
+
+fn read_header<G: Variant>(reader: &mut ByteReader, kind: u8) -> Result<(), String> {
+    let version = reader.u8()?;
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported format version {} (expected {})", version, FORMAT_VERSION));
+    }
+    let found = reader.u8()?;
+    if found != kind {
+        return Err(format!("Expected record kind '{}', found '{}'", kind as char, found as char));
+    }
+    let size = reader.u8()? as usize;
+    if size != G::SIZE {
+        return Err(format!("Board size {} does not match {} ({})", size, G::NAME, G::SIZE));
+    }
+    Ok(())
+}
+
+fn bitset_len<G: Variant>() -> usize {
+    (G::SIZE * G::SIZE).div_ceil(8)
+}
+
+fn write_position<G: Variant>(game: &G, bytes: &mut Vec<u8>) {
+    for piece in PIECE_SETS {
+        let mut bits = vec![0u8; bitset_len::<G>()];
+        for row in 0..G::SIZE {
+            for col in 0..G::SIZE {
+                if game.cell((row, col)).cell_type == piece {
+                    let index = row * G::SIZE + col;
+                    bits[index / 8] |= 1 << (index % 8);
+                }
+            }
+        }
+        bytes.extend_from_slice(&bits);
+    }
+
+    let side = u8::from(game.side_to_move() != CellType::Attacker);
+    let status = match game.winner() {
+        None => 0,
+        Some(CellType::Attacker) => 1,
+        Some(CellType::Defender) | Some(CellType::King) => 2,
+        Some(CellType::Empty) => 3,
+    };
+    bytes.push(side | status << 1);
+
+    let (attacker_moves, defender_moves) = game.side_move_counts();
+    bytes.extend_from_slice(&(attacker_moves.min(u16::MAX as u32) as u16).to_le_bytes());
+    bytes.extend_from_slice(&(defender_moves.min(u16::MAX as u32) as u16).to_le_bytes());
+}
+
+fn read_position<G: Variant>(reader: &mut ByteReader) -> Result<G, String> {
+    let mut game = G::empty_board();
+
+    let mut kings = 0;
+    for piece in PIECE_SETS {
+        let bits = reader.bytes(bitset_len::<G>())?;
+        for index in 0..G::SIZE * G::SIZE {
+            if bits[index / 8] & (1 << (index % 8)) == 0 {
+                continue;
+            }
+            let pos = (index / G::SIZE, index % G::SIZE);
+            let cell = game.cell(pos);
+            if cell.cell_type != CellType::Empty {
+                return Err(format!("Square {:?} holds more than one piece", pos));
+            }
+            game.set_cell(pos, Cell { cell_type: piece, ..cell });
+            if piece == CellType::King {
+                kings += 1;
+            }
+        }
+        let used_bits = (G::SIZE * G::SIZE) % 8;
+        if used_bits != 0 && bits[bits.len() - 1] >> used_bits != 0 {
+            return Err("Non-zero padding bits in piece set".to_string());
+        }
+    }
+    if kings > 1 {
+        return Err(format!("Position has {} kings", kings));
+    }
+
+    let status = reader.u8()?;
+    game.set_side_to_move(if status & 1 == 0 { CellType::Attacker } else { CellType::Defender });
+    game.set_winner(match (status >> 1) & 0b11 {
+        0 => None,
+        1 => Some(CellType::Attacker),
+        2 => Some(CellType::Defender),
+        _ => Some(CellType::Empty),
+    });
+    if status >> 3 != 0 {
+        return Err(format!("Invalid status byte {:#04x}", status));
+    }
+
+    let attacker_moves = reader.u16()? as u32;
+    let defender_moves = reader.u16()? as u32;
+    game.set_side_move_counts(attacker_moves, defender_moves);
+    Ok(game)
+}
+
+fn square_index<G: Variant>(pos: (usize, usize)) -> Result<u8, String> {
+    if pos.0 >= G::SIZE || pos.1 >= G::SIZE {
+        return Err(format!("Square {:?} is off the board", pos));
+    }
+    Ok((pos.0 * G::SIZE + pos.1) as u8)
+}
+
+fn square<G: Variant>(index: u8) -> Result<(usize, usize), String> {
+    let index = index as usize;
+    if index >= G::SIZE * G::SIZE {
+        return Err(format!("Square index {} is off the board", index));
+    }
+    Ok((index / G::SIZE, index % G::SIZE))
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err("Unexpected end of data".to_string());
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.pos != self.bytes.len() {
+            return Err(format!("{} trailing bytes", self.bytes.len() - self.pos));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brandubh;
+    use crate::copenhagen;
+    use crate::test_util::random_moves;
+
+    /// The start position and one a few moves into a game.
+    fn positions<G: Variant>() -> Vec<G> {
+        let mut mid_game = G::new_game();
+        for mv in random_moves::<G>(12, 7) {
+            mid_game.play(mv).unwrap();
+        }
+        assert!(mid_game.move_count() > 0);
+        vec![G::new_game(), mid_game]
+    }
+
+    fn assert_same_position<G: Variant>(expected: &G, actual: &G) {
+        for row in 0..G::SIZE {
+            for col in 0..G::SIZE {
+                assert_eq!(expected.cell((row, col)).cell_type, actual.cell((row, col)).cell_type);
+            }
+        }
+        assert_eq!(expected.side_to_move(), actual.side_to_move());
+        assert_eq!(expected.side_move_counts(), actual.side_move_counts());
+        assert_eq!(expected.outcome(), actual.outcome());
+    }
+
+    fn check_binary_round_trip<G: Variant>() {
+        for position in positions::<G>() {
+            let bytes = encode_position(&position);
+            assert_eq!(&bytes[..3], &[FORMAT_VERSION, KIND_POSITION, G::SIZE as u8]);
+            let decoded: G = decode_position(&bytes).unwrap();
+            assert_same_position(&position, &decoded);
+            assert_eq!(encode_position(&decoded), bytes);
+        }
+    }
+
+    fn check_text_round_trip<G: Variant>() {
+        for position in positions::<G>() {
+            let text = position_to_text(&position);
+            let decoded: G = position_from_text(&text).unwrap();
+            assert_eq!(position_to_text(&decoded), text);
+            // The text notation leaves out the move counters
+            assert_eq!(decoded.move_count(), 0);
+            for mv in position.legal_moves() {
+                assert_eq!(move_from_text::<G>(&move_to_text(mv)), Ok(mv));
+            }
+        }
+    }
+
+    fn check_game_round_trip<G: Variant>() {
+        let start = G::new_game();
+        let moves = random_moves::<G>(40, 11);
+        let bytes = encode_game(&start, &moves).unwrap();
+        assert_eq!(&bytes[..3], &[FORMAT_VERSION, KIND_GAME, G::SIZE as u8]);
+        let (decoded_start, decoded_moves) = decode_game::<G>(&bytes).unwrap();
+        assert_same_position(&start, &decoded_start);
+        assert_eq!(decoded_moves, moves);
+
+        let mut archive = Vec::new();
+        write_record(&mut archive, &bytes).unwrap();
+        write_record(&mut archive, &encode_position(&start)).unwrap();
+        let mut reader = archive.as_slice();
+        assert_eq!(read_record(&mut reader).unwrap(), Some(bytes));
+        assert!(read_record(&mut reader).unwrap().is_some());
+        assert_eq!(read_record(&mut reader).unwrap(), None);
+    }
+
+    fn error<T>(result: Result<T, String>) -> String {
+        match result {
+            Ok(_) => panic!("Expected an error"),
+            Err(err) => err,
+        }
+    }
+
+    fn check_rejects_bad_headers<G: Variant>() {
+        let position = encode_position(&G::new_game());
+        let game = encode_game(&G::new_game(), &random_moves::<G>(4, 3)).unwrap();
+
+        let mut wrong_version = position.clone();
+        wrong_version[0] = FORMAT_VERSION + 1;
+        assert!(error(decode_position::<G>(&wrong_version)).contains("format version"));
+        let mut wrong_version = game.clone();
+        wrong_version[0] = FORMAT_VERSION + 1;
+        assert!(error(decode_game::<G>(&wrong_version)).contains("format version"));
+
+        // A game is not a position and vice versa
+        assert!(error(decode_position::<G>(&game)).contains("record kind"));
+        assert!(error(decode_game::<G>(&position)).contains("record kind"));
+        let mut wrong_kind = position.clone();
+        wrong_kind[1] = b'X';
+        assert!(error(decode_position::<G>(&wrong_kind)).contains("record kind"));
+
+        assert!(decode_position::<G>(&position[..position.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn positions_round_trip_through_binary() {
+        check_binary_round_trip::<brandubh::GameState>();
+        check_binary_round_trip::<copenhagen::GameState>();
+    }
+
+    #[test]
+    fn positions_round_trip_through_text() {
+        check_text_round_trip::<brandubh::GameState>();
+        check_text_round_trip::<copenhagen::GameState>();
+    }
+
+    #[test]
+    fn games_round_trip() {
+        check_game_round_trip::<brandubh::GameState>();
+        check_game_round_trip::<copenhagen::GameState>();
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        check_rejects_bad_headers::<brandubh::GameState>();
+        check_rejects_bad_headers::<copenhagen::GameState>();
+    }
+
+    #[test]
+    fn positions_of_another_board_size_are_rejected() {
+        let bytes = encode_position(&brandubh::GameState::new_game());
+        assert!(decode_position::<copenhagen::GameState>(&bytes).unwrap_err().contains("Board size"));
+    }
+
+    #[test]
+    fn start_position_text() {
+        let start = brandubh::GameState::new_game();
+        assert_eq!(position_to_text(&start), "...A.../...A.../...D.../AADKDAA/...D.../...A.../...A... a");
+        assert_eq!(move_to_text(Move { from: (0, 3), to: (0, 1) }), "d1-b1");
+    }
+}
@@ -0,0 +1,415 @@
+//! Line-based engine protocol over stdin/stdout, in the style of UCI, so that
+//! engines written in any language can play through the crate, and the
+//! crate's own engine can be driven by other programs.
+//!
+//! The controller sends one command per line; the engine answers with lines
+//! of its own. Positions and moves use the text notation of `encoding`.
+//!
+//! Controller to engine:
+//!
+//! - `tei`: start of the session. The engine answers `id name NAME`,
+//!   optionally other `id` lines, then `teiok`.
+//! - `isready`: the engine answers `readyok` once it is done with earlier commands.
+//! - `variant brandubh|copenhagen`: rules for the following commands
+//!   (Brandubh until the first `variant`). Resets the position.
+//! - `newgame`: a new game starts; the engine may forget what it learned.
+//! - `threads N`: search with N threads from now on. Engines that cannot
+//!   search in parallel may ignore it.
+//! - `position startpos [moves M1 M2 ...]` or
+//!   `position BOARD SIDE [ATTACKER_MOVES DEFENDER_MOVES] [moves M1 M2 ...]`:
+//!   the position to search, with the move counters (0 if left out) that the
+//!   100-move limit depends on, and moves played from it.
+//! - `go [depth N] [movetime MS] [nodes N]`: search the position. The engine
+//!   may send `info` lines, then sends `bestmove MOVE`, or `bestmove none`
+//!   when there is no legal move or the game is over.
+//! - `quit`: the engine exits.
+//!
+//! Engine to controller, besides the answers above:
+//!
+//! - `info depth D score cp S|win N|loss N nodes N time MS pv M1 M2 ...`:
+//!   progress of a search, with scores from the side to move's point of view
+//!   and wins or losses counted in plies.
+//! - `info string TEXT`: anything for a human reader, such as errors.
+//!
+//! Unknown commands are answered with `info string` and otherwise ignored.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::agent::{Agent, Clock};
+use crate::brandubh;
+use crate::copenhagen;
+use crate::encoding;
+use crate::eval::{WeightedEvaluator, Weights};
+use crate::search::{SearchEngine, SearchInfo, SearchLimits, WIN_SCORE, WIN_THRESHOLD};
+use crate::variant::{Move, Variant};
+
+/// Name the built-in engine gives in its `id name` line.
+pub const ENGINE_NAME: &str = "hnefatafl-search";
+
+/// Search depth of a `go` without any limit.
+const DEFAULT_GO_DEPTH: u32 = 4;
+
+/// What ended one variant's part of a session.
+enum SessionEnd {
+    Variant(String),
+    Quit,
+}
+
+/// Runs the built-in search engine on the protocol until `quit` or the end of
+/// `input`, searching with `threads` threads until told otherwise.
+pub fn run_engine(input: impl BufRead, mut output: impl Write, weights: Weights, mut threads: usize) -> io::Result<()> {
+    let mut lines = input.lines();
+    let mut variant = "brandubh".to_string();
+    loop {
+        let end = match variant.as_str() {
+            "brandubh" => serve::<brandubh::GameState>(&mut lines, &mut output, weights, &mut threads)?,
+            "copenhagen" => serve::<copenhagen::GameState>(&mut lines, &mut output, weights, &mut threads)?,
+            _ => unreachable!("Variants are checked when they are set"),
+        };
+        match end {
+            SessionEnd::Variant(name) => variant = name,
+            SessionEnd::Quit => return Ok(()),
+        }
+    }
+}
+
+/// Answers commands for one variant, until another variant is asked for.
+fn serve<G: Variant>(
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+    output: &mut impl Write,
+    weights: Weights,
+    threads: &mut usize,
+) -> io::Result<SessionEnd> {
+    let mut engine = SearchEngine::<G, _>::new(WeightedEvaluator::new(weights));
+    engine.set_threads(*threads);
+    let mut position = G::new_game();
+
+    for line in lines {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let args: Vec<&str> = words.collect();
+        match command {
+            "tei" => {
+                writeln!(output, "id name {}", ENGINE_NAME)?;
+                writeln!(output, "id version {}", env!("CARGO_PKG_VERSION"))?;
+                writeln!(output, "teiok")?;
+            }
+            "isready" => writeln!(output, "readyok")?,
+            "variant" => match args.first().map(|name| name.to_lowercase()) {
+                Some(name) if name == "brandubh" || name == "copenhagen" => {
+                    if name == G::NAME.to_lowercase() {
+                        position = G::new_game();
+                    } else {
+                        return Ok(SessionEnd::Variant(name));
+                    }
+                }
+                _ => writeln!(output, "info string Unknown variant: {}", args.join(" "))?,
+            },
+            "newgame" => {
+                engine.clear();
+                position = G::new_game();
+            }
+            "threads" => match args.first().and_then(|count| count.parse::<usize>().ok()) {
+                Some(count) if count > 0 => {
+                    engine.set_threads(count);
+                    *threads = count;
+                }
+                _ => writeln!(output, "info string Invalid thread count: {}", args.join(" "))?,
+            },
+            "position" => match parse_position::<G>(&args) {
+                Ok(parsed) => position = parsed,
+                Err(err) => writeln!(output, "info string Invalid position: {}", err)?,
+            },
+            "go" => match parse_go(&args) {
+                Ok(limits) => {
+                    let info = engine.search_with_progress(&position, &limits, |info| {
+                        // A closed output shows up again on the `bestmove` line
+                        let _ = writeln!(output, "{}", info_line(info));
+                    });
+                    match info.best_move {
+                        Some(mv) => writeln!(output, "bestmove {}", encoding::move_to_text(mv))?,
+                        None => writeln!(output, "bestmove none")?,
+                    }
+                }
+                Err(err) => writeln!(output, "info string Invalid go command: {}", err)?,
+            },
+            "quit" => return Ok(SessionEnd::Quit),
+            _ => writeln!(output, "info string Unknown command: {}", command)?,
+        }
+        output.flush()?;
+    }
+    Ok(SessionEnd::Quit)
+}
+
+/// Parses the arguments of a `position` command.
+fn parse_position<G: Variant>(args: &[&str]) -> Result<G, String> {
+    let split = args.iter().position(|&word| word == "moves").unwrap_or(args.len());
+    let (setup, moves) = (&args[..split], args.get(split + 1..).unwrap_or_default());
+
+    let mut position = match setup {
+        ["startpos"] => G::new_game(),
+        [board, side] => encoding::position_from_text(&format!("{} {}", board, side))?,
+        [board, side, attacker_moves, defender_moves] => {
+            let mut position: G = encoding::position_from_text(&format!("{} {}", board, side))?;
+            let count = |text: &str| text.parse::<u32>().map_err(|_| format!("Invalid move count: {}", text));
+            position.set_side_move_counts(count(attacker_moves)?, count(defender_moves)?);
+            position
+        }
+        _ => return Err("Expected startpos or BOARD SIDE [ATTACKER_MOVES DEFENDER_MOVES]".to_string()),
+    };
+    for text in moves {
+        let mv = encoding::move_from_text::<G>(text)?;
+        position.play(mv).map_err(|err| format!("Move {} is illegal: {}", text, err))?;
+    }
+    Ok(position)
+}
+
+/// Parses the arguments of a `go` command.
+fn parse_go(args: &[&str]) -> Result<SearchLimits, String> {
+    let mut limits = SearchLimits::default();
+    let mut limited = false;
+    for pair in args.chunks(2) {
+        let [name, value] = pair else {
+            return Err(format!("Missing value for {}", pair[0]));
+        };
+        let number = value.parse::<u64>().map_err(|_| format!("Invalid value for {}: {}", name, value))?;
+        match *name {
+            "depth" => limits.max_depth = number.min(u32::MAX as u64) as u32,
+            "movetime" => limits.time = Some(Duration::from_millis(number)),
+            "nodes" => limits.nodes = Some(number),
+            _ => return Err(format!("Unknown limit: {}", name)),
+        }
+        limited = true;
+    }
+    if !limited {
+        limits.max_depth = DEFAULT_GO_DEPTH;
+    }
+    Ok(limits)
+}
+
+fn info_line(info: &SearchInfo) -> String {
+    let score = if info.score >= WIN_THRESHOLD {
+        format!("win {}", WIN_SCORE - info.score)
+    } else if info.score <= -WIN_THRESHOLD {
+        format!("loss {}", WIN_SCORE + info.score)
+    } else {
+        format!("cp {}", info.score)
+    };
+    let pv: Vec<String> = info.pv.iter().map(|&mv| encoding::move_to_text(mv)).collect();
+    format!(
+        "info depth {} score {} nodes {} time {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        info.elapsed.as_millis(),
+        pv.join(" ")
+    )
+}
+
+/// How long an external engine may take to start or to answer `isready`.
+const ENGINE_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long an external engine may take past its `movetime` to send `bestmove`.
+const ENGINE_MOVE_MARGIN: Duration = Duration::from_secs(2);
+/// How long an external engine may search without a `movetime`, if not set otherwise.
+pub const DEFAULT_SEARCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An engine running as a child process, driven through the protocol.
+///
+/// The engine's output is read on a thread of its own, so that an engine that
+/// stops answering is noticed: it is killed once it misses a deadline, and
+/// the call waiting for it fails.
+pub struct ExternalEngine {
+    child: Child,
+    input: ChildStdin,
+    lines: Receiver<String>,
+    name: String,
+    search_timeout: Duration,
+}
+
+impl ExternalEngine {
+    /// Starts `command` (a path to the executable, followed by its arguments,
+    /// separated by spaces) and waits for its `teiok`.
+    pub fn start(command: &str) -> Result<Self, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("Empty engine command")?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Cannot start engine {}: {}", program, e))?;
+        let input = child.stdin.take().expect("Engine stdin is piped");
+        let output = BufReader::new(child.stdout.take().expect("Engine stdout is piped"));
+        let (sender, lines) = mpsc::channel();
+        // Ends when the engine exits or the engine handle is dropped
+        thread::spawn(move || {
+            for line in output.lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = ExternalEngine {
+            child,
+            input,
+            lines,
+            name: program.to_string(),
+            search_timeout: DEFAULT_SEARCH_TIMEOUT,
+        };
+
+        engine.send("tei")?;
+        let deadline = Instant::now() + ENGINE_REPLY_TIMEOUT;
+        loop {
+            let line = engine.read_line(deadline)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if line.trim() == "teiok" {
+                return Ok(engine);
+            }
+        }
+    }
+
+    /// The name the engine gave in its `id name` line.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sets how long a search without a time limit (only `depth` or `nodes`)
+    /// may take before the engine is killed.
+    pub fn set_search_timeout(&mut self, timeout: Duration) {
+        self.search_timeout = timeout;
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.input, "{}", command)
+            .and_then(|_| self.input.flush())
+            .map_err(|e| format!("Cannot write to engine {}: {}", self.name, e))
+    }
+
+    /// Waits for `readyok`, for up to `ENGINE_REPLY_TIMEOUT`.
+    pub fn wait_ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        let deadline = Instant::now() + ENGINE_REPLY_TIMEOUT;
+        while self.read_line(deadline)?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    /// Asks for the best move in `position` within `limits`. `None` means the
+    /// engine found no move (`bestmove none`). An engine that has not answered
+    /// `ENGINE_MOVE_MARGIN` past its time limit, or within the search timeout
+    /// if there is none, is killed.
+    pub fn best_move<G: Variant>(&mut self, position: &G, limits: &SearchLimits) -> Result<Option<Move>, String> {
+        let (attacker_moves, defender_moves) = position.side_move_counts();
+        self.send(&format!(
+            "position {} {} {}",
+            encoding::position_to_text(position),
+            attacker_moves,
+            defender_moves
+        ))?;
+        let mut go = format!("go depth {}", limits.max_depth);
+        if let Some(time) = limits.time {
+            go += &format!(" movetime {}", time.as_millis());
+        }
+        if let Some(nodes) = limits.nodes {
+            go += &format!(" nodes {}", nodes);
+        }
+        self.send(&go)?;
+
+        let deadline = Instant::now() + limits.time.map_or(self.search_timeout, |time| time + ENGINE_MOVE_MARGIN);
+        loop {
+            let line = self.read_line(deadline)?;
+            let Some(answer) = line.strip_prefix("bestmove ") else {
+                continue;
+            };
+            return match answer.trim() {
+                "none" => Ok(None),
+                text => encoding::move_from_text::<G>(text)
+                    .map(Some)
+                    .map_err(|err| format!("Engine {} sent {}: {}", self.name, line.trim(), err)),
+            };
+        }
+    }
+
+    /// The engine's next line, waiting until `deadline`. An engine that
+    /// misses the deadline is killed.
+    fn read_line(&mut self, deadline: Instant) -> Result<String, String> {
+        match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Disconnected) => Err(format!("Engine {} exited", self.name)),
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                Err(format!("Engine {} did not answer in time and was stopped", self.name))
+            }
+        }
+    }
+}
+
+impl Drop for ExternalEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // Give the engine a moment to exit by itself before stopping it
+        for _ in 0..10 {
+            if self.child.try_wait().ok().flatten().is_some() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Plays the moves of an external engine.
+pub struct ExternalAgent<G: Variant> {
+    engine: ExternalEngine,
+    limits: SearchLimits,
+    _variant: std::marker::PhantomData<fn() -> G>,
+}
+
+impl<G: Variant> ExternalAgent<G> {
+    /// Starts the engine with `command` (see `ExternalEngine::start`) and sets
+    /// it up for `G`. `limits` apply to moves without a time budget.
+    pub fn start(command: &str, limits: SearchLimits) -> Result<Self, String> {
+        let mut engine = ExternalEngine::start(command)?;
+        engine.send(&format!("variant {}", G::NAME.to_lowercase()))?;
+        engine.send("newgame")?;
+        engine.wait_ready()?;
+        Ok(ExternalAgent {
+            engine,
+            limits,
+            _variant: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<G: Variant> Agent<G> for ExternalAgent<G> {
+    fn name(&self) -> String {
+        self.engine.name().to_string()
+    }
+
+    /// Panics if the engine fails; use `try_choose_move` to handle that.
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move {
+        self.try_choose_move(position, clock).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Fails if the engine exits, misses its deadline, sends a move that
+    /// cannot be read or finds no move.
+    fn try_choose_move(&mut self, position: &G, clock: &Clock) -> Result<Move, String> {
+        let mut limits = self.limits;
+        if let Some(budget) = clock.budget() {
+            limits.time = Some(limits.time.map_or(budget, |time| time.min(budget)));
+        }
+        self.engine
+            .best_move(position, &limits)?
+            .ok_or_else(|| format!("Engine {} found no move", self.engine.name()))
+    }
+}
@@ -0,0 +1,288 @@
+//! Heuristic evaluation as a weighted sum of named features.
+//!
+//! Every feature is measured from the attackers' point of view (a positive
+//! weight means the feature is good for the attackers), so the same weights
+//! serve both sides. Weights can be saved to and loaded from a text file with
+//! one `name = weight` line per feature:
+//!
+//! ```text
+//! # Comments and blank lines are ignored
+//! attacker_material = 100
+//! defender_material = -200
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::ops::{Index, IndexMut};
+use std::path::Path;
+
+use crate::cell::CellType;
+use crate::search::Evaluator;
+use crate::tactics;
+use crate::variant::Variant;
+
+pub const FEATURE_COUNT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    AttackerMaterial,   // Attackers on the board
+    DefenderMaterial,   // Defenders on the board, not counting the king
+    KingCornerDistance, // Orthogonal distance from the king to the nearest corner
+    KingEscapeRoutes,   // Corners the king can reach in one or two moves
+    KingFreedom,        // Squares the king can move to
+    KingPressure,       // Attackers orthogonally next to the king
+    Mobility,           // Attacker moves minus defender (and king) moves
+    CornerControl,      // Attackers minus defenders on the squares next to the corners
+}
+
+impl Feature {
+    pub const ALL: [Feature; FEATURE_COUNT] = [
+        Feature::AttackerMaterial,
+        Feature::DefenderMaterial,
+        Feature::KingCornerDistance,
+        Feature::KingEscapeRoutes,
+        Feature::KingFreedom,
+        Feature::KingPressure,
+        Feature::Mobility,
+        Feature::CornerControl,
+    ];
+
+    /// Name used in weight files and breakdowns.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::AttackerMaterial => "attacker_material",
+            Feature::DefenderMaterial => "defender_material",
+            Feature::KingCornerDistance => "king_corner_distance",
+            Feature::KingEscapeRoutes => "king_escape_routes",
+            Feature::KingFreedom => "king_freedom",
+            Feature::KingPressure => "king_pressure",
+            Feature::Mobility => "mobility",
+            Feature::CornerControl => "corner_control",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Feature> {
+        Feature::ALL.into_iter().find(|feature| feature.name() == name)
+    }
+}
+
+/// Value of every feature of a position, indexed like `Feature::ALL`.
+pub type FeatureValues = [i32; FEATURE_COUNT];
+
+/// Measures every feature of `position`, from the attackers' point of view.
+pub fn features<G: Variant>(position: &G) -> FeatureValues {
+    let mut values = [0; FEATURE_COUNT];
+    let corners = tactics::corners::<G>();
+    let mut mobility = 0;
+
+    for row in 0..G::SIZE {
+        for col in 0..G::SIZE {
+            let cell_type = position.cell((row, col)).cell_type;
+            let moves = match cell_type {
+                CellType::Empty => continue,
+                _ => position.valid_moves_from((row, col)).len() as i32,
+            };
+            match cell_type {
+                CellType::Attacker => {
+                    values[Feature::AttackerMaterial as usize] += 1;
+                    mobility += moves;
+                }
+                CellType::Defender => {
+                    values[Feature::DefenderMaterial as usize] += 1;
+                    mobility -= moves;
+                }
+                _ => mobility -= moves,
+            }
+        }
+    }
+    values[Feature::Mobility as usize] = mobility;
+
+    for pos in corners.iter().flat_map(|&corner| orthogonal_neighbours::<G>(corner)) {
+        match position.cell(pos).cell_type {
+            CellType::Attacker => values[Feature::CornerControl as usize] += 1,
+            CellType::Defender | CellType::King => values[Feature::CornerControl as usize] -= 1,
+            CellType::Empty => {}
+        }
+    }
+
+    if let Some(king) = position.king_position() {
+        let last = G::SIZE - 1;
+        values[Feature::KingCornerDistance as usize] = (king.0.min(last - king.0) + king.1.min(last - king.1)) as i32;
+
+        values[Feature::KingFreedom as usize] = position.valid_moves_from(king).len() as i32;
+        let routes = tactics::escape_routes(position);
+        values[Feature::KingEscapeRoutes as usize] = corners
+            .iter()
+            .filter(|&&corner| routes.iter().any(|route| route.corner == corner))
+            .count() as i32;
+        values[Feature::KingPressure as usize] = orthogonal_neighbours::<G>(king)
+            .filter(|&pos| position.cell(pos).cell_type == CellType::Attacker)
+            .count() as i32;
+    }
+    values
+}
+
+fn orthogonal_neighbours<G: Variant>(pos: (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
+    [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter().filter_map(move |(d_row, d_col)| {
+        let row = pos.0.checked_add_signed(d_row)?;
+        let col = pos.1.checked_add_signed(d_col)?;
+        (row < G::SIZE && col < G::SIZE).then_some((row, col))
+    })
+}
+
+/// One weight per feature, in the same units as search scores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weights(pub [i32; FEATURE_COUNT]);
+
+impl Default for Weights {
+    /// Hand-picked starting weights, close to `SimpleEvaluator` for the
+    /// features the two share.
+    fn default() -> Self {
+        let mut weights = Weights([0; FEATURE_COUNT]);
+        weights[Feature::AttackerMaterial] = 100;
+        weights[Feature::DefenderMaterial] = -200;
+        weights[Feature::KingCornerDistance] = 25;
+        weights[Feature::KingEscapeRoutes] = -150;
+        weights[Feature::KingFreedom] = -10;
+        weights[Feature::KingPressure] = 40;
+        weights[Feature::Mobility] = 5;
+        weights[Feature::CornerControl] = 30;
+        weights
+    }
+}
+
+impl Index<Feature> for Weights {
+    type Output = i32;
+
+    fn index(&self, feature: Feature) -> &i32 {
+        &self.0[feature as usize]
+    }
+}
+
+impl IndexMut<Feature> for Weights {
+    fn index_mut(&mut self, feature: Feature) -> &mut i32 {
+        &mut self.0[feature as usize]
+    }
+}
+
+impl Weights {
+    /// Parses a weights file. Features it does not mention keep their default weight.
+    pub fn parse(text: &str) -> Result<Weights, String> {
+        let mut weights = Weights::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or(format!("Line {}: expected `name = weight`", number + 1))?;
+            let feature = Feature::from_name(name.trim())
+                .ok_or(format!("Line {}: unknown feature '{}'", number + 1, name.trim()))?;
+            weights[feature] = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Line {}: invalid weight '{}'", number + 1, value.trim()))?;
+        }
+        Ok(weights)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Weights, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        Weights::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Attackers' score for the given feature values.
+    pub fn score(&self, values: &FeatureValues) -> i32 {
+        values.iter().zip(self.0).map(|(value, weight)| value * weight).sum()
+    }
+}
+
+/// The weights file format, one `name = weight` line per feature.
+impl fmt::Display for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for feature in Feature::ALL {
+            writeln!(f, "{} = {}", feature.name(), self[feature])?;
+        }
+        Ok(())
+    }
+}
+
+/// Contribution of one feature to an evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Term {
+    pub feature: Feature,
+    pub value: i32,
+    pub weight: i32,
+    pub score: i32, // `value * weight`
+}
+
+/// Per-feature breakdown of an evaluation, for debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakdown {
+    pub side: CellType,   // Side the total is given for
+    pub terms: Vec<Term>, // Attackers' point of view
+    pub total: i32,
+}
+
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<22}{:>8}{:>8}{:>8}", "feature", "value", "weight", "score")?;
+        for term in &self.terms {
+            writeln!(f, "{:<22}{:>8}{:>8}{:>8}", term.feature.name(), term.value, term.weight, term.score)?;
+        }
+        write!(f, "total for {}: {}", self.side, self.total)
+    }
+}
+
+/// `Evaluator` scoring positions with a set of feature weights.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WeightedEvaluator {
+    weights: Weights,
+}
+
+impl WeightedEvaluator {
+    pub fn new(weights: Weights) -> Self {
+        WeightedEvaluator { weights }
+    }
+
+    pub fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    /// The evaluation of `position` for `side`, feature by feature.
+    pub fn breakdown<G: Variant>(&self, position: &G, side: CellType) -> Breakdown {
+        let values = features(position);
+        let terms: Vec<Term> = Feature::ALL
+            .into_iter()
+            .map(|feature| {
+                let value = values[feature as usize];
+                let weight = self.weights[feature];
+                Term {
+                    feature,
+                    value,
+                    weight,
+                    score: value * weight,
+                }
+            })
+            .collect();
+        let attacker_score: i32 = terms.iter().map(|term| term.score).sum();
+        Breakdown {
+            side,
+            terms,
+            total: if side == CellType::Attacker { attacker_score } else { -attacker_score },
+        }
+    }
+}
+
+impl<G: Variant> Evaluator<G> for WeightedEvaluator {
+    fn evaluate(&self, position: &G, side: CellType) -> i32 {
+        let attacker_score = self.weights.score(&features(position));
+        if side == CellType::Attacker { attacker_score } else { -attacker_score }
+    }
+}
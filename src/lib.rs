@@ -0,0 +1,24 @@
+pub mod agent;
+pub mod analysis;
+pub mod book;
+pub mod brandubh;
+pub mod cell;
+pub mod copenhagen;
+pub mod dataset;
+pub mod encoding;
+pub mod engine;
+pub mod eval;
+pub mod mcts;
+pub mod protocol;
+pub mod rng;
+pub mod search;
+pub mod solver;
+pub mod stats;
+pub mod strength;
+pub mod symmetry;
+pub mod tablebase;
+pub mod tactics;
+#[cfg(test)]
+mod test_util;
+pub mod tt;
+pub mod variant;
@@ -1,60 +1,59 @@
 use std::collections::HashMap;
-use std::io::{self, Read, Write};
+use std::io::{self, BufRead, BufReader, Write};
 use std::net::{TcpListener, TcpStream, Shutdown};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread;
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 // 7x7 version
-mod brandubh;
-use brandubh::{GameState, Cell, CellType};
+use hnefatafl::brandubh::{GameState, Cell, CellType};
 
 // 11x11 version
-//mod copenhagen;
-//use copenhagen::{GameState, Cell, CellType};
-
+//use hnefatafl::copenhagen::{GameState, Cell, CellType};
+
+
+use hnefatafl::agent::{Agent, Clock, RandomAgent};
+use hnefatafl::analysis;
+use hnefatafl::encoding;
+use hnefatafl::engine::ExternalAgent;
+use hnefatafl::mcts::{HeuristicRollout, MctsAgent, MctsConfig};
+use hnefatafl::protocol::{
+    self, AnalysisOptions, BoardState, BotRequest, ClientMessage, Feature, Hello, LevelValue, ServerMessage, Welcome,
+    PROTOCOL_VERSION,
+};
+use hnefatafl::rng;
+use hnefatafl::search::SearchLimits;
+use hnefatafl::stats::{GameRecord, GameStats};
+use hnefatafl::strength::{Level, StrengthAgent};
+use hnefatafl::variant::{opponent, Move, Variant};
 
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::time::{Instant, Duration};
+use std::time::Instant;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Move {
-    from: (usize, usize),
-    to: (usize, usize),
-}
+const DEFAULT_ANALYSIS_DEPTH: u32 = 4;
+const MAX_ANALYSIS_TIME: Duration = Duration::from_secs(10);
+const MAX_ANALYSIS_LINES: usize = 10;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct BoardState {
-    board: HashMap<String, CellType>,
-}
+/// External engines clients may play against, by name: the command that starts each.
+type EngineCommands = HashMap<String, String>;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct GameStateResponse {
-    board_state: BoardState,
-    current_turn: CellType,
-    winner: Option<CellType>,
-}
+const USAGE: &str = "Usage: hnefatafl [--engine NAME=COMMAND ...]";
 
-struct GameStats {
-    game_id: u32,
-    total_games: u32,
-    total_attacker_moves: u32,
-    total_defender_moves: u32,
-    attacker_wins: u32,
-    defender_wins: u32,
-    ties: u32,
-    move_durations_attacker: Vec<Duration>,
-    move_durations_defender: Vec<Duration>,
-    attacker_moves: Vec<u32>,
-    defender_moves: Vec<u32>,
-}
+const DEFAULT_BOT_LEVEL: Level = Level::Medium;
+
+/// How long a new connection may take to send a `PlayBot` (or an `Analyse`)
+/// request before it is paired with another client instead.
+const BOT_REQUEST_WAIT: Duration = Duration::from_millis(500);
+// Optional protocol features this server offers to clients that ask for them
+const SERVER_FEATURES: &[Feature] = &[Feature::LegalMoves];
 
 fn process_move(
     game: &mut GameState,
     game_move: Move,
     role: CellType,
-    clients: &Arc<Mutex<HashMap<usize, TcpStream>>>,
+    clients: &Arc<Mutex<HashMap<usize, SessionClient>>>,
     stats: &Arc<Mutex<GameStats>>
 ) -> Result<(), String> {
     if game.current_turn.cell_type != role {
@@ -113,30 +112,14 @@ fn process_move(
         });
     }
 
-    let mut board_state = HashMap::new();
-    for (row_idx, row) in game.board.iter().enumerate() {
-        for (col_idx, cell) in row.iter().enumerate() {
-            let key = format!("({}, {})", row_idx, col_idx);
-            board_state.insert(key, cell.cell_type);
-        }
-    }
-
-    let response = GameStateResponse {
-        board_state: BoardState { board: board_state },
-        current_turn: game.current_turn.cell_type,
-        winner: game.winner.map(|cell| cell.cell_type),
-    };
+    let board_state_json = serde_json::to_string(&BoardState::from_position(&*game)).expect("Unable to serialize board state");
 
-    let response_json = serde_json::to_string(&response).map_err(|e| e.to_string())?;
-    let board_state_json = serde_json::to_string(&response.board_state).expect("Unable to serialize board state");
-    
     let file_name = {
         let stats_lock = stats.lock().unwrap();
         format!("results/board_state_session_{}_game_{}.txt", stats_lock.game_id, stats_lock.total_games + 1)
     };
 
     let mut file = OpenOptions::new()
-        .write(true)
         .append(true)
         .open(&file_name)
         .map_err(|e| format!("Failed to open {}: {}", file_name, e))?;
@@ -147,8 +130,8 @@ fn process_move(
     drop(file);
 
     let clients_lock = clients.lock().unwrap();
-    for (id, mut client_stream) in clients_lock.iter() {
-        if let Err(e) = client_stream.write_all(response_json.as_bytes()) {
+    for (id, client) in clients_lock.iter() {
+        if let Err(e) = send_message(&client.stream, &state_message(game, client.legal_moves)) {
             eprintln!("Failed to write to client {}: {}", id, e);
         }
     }
@@ -159,7 +142,6 @@ fn process_move(
         println!("Defender moves: {}", game.defender_moves);
 
         let mut file = OpenOptions::new()
-            .write(true)
             .append(true)
             .open(&file_name)
             .map_err(|e| format!("Failed to open {}: {}", file_name, e))?;
@@ -173,24 +155,102 @@ fn process_move(
     Ok(())
 }
 
+/// Sends one message to a client as a line of its own, written in a single
+/// call so that messages from different threads do not interleave.
+fn send_message(mut stream: &TcpStream, message: &ServerMessage) -> io::Result<()> {
+    stream.write_all(protocol::to_line(message).as_bytes())
+}
+
+/// The `GameState` message for `game`, with the legal moves of the side to
+/// move if `legal_moves` is set, as found by the server's own rules.
+fn state_message(game: &GameState, legal_moves: bool) -> ServerMessage {
+    ServerMessage::GameState {
+        board_state: BoardState::from_position(game),
+        current_turn: game.current_turn.cell_type,
+        winner: game.winner.map(|cell| cell.cell_type),
+        legal_moves: legal_moves.then(|| if game.winner.is_some() { Vec::new() } else { game.legal_moves() }),
+    }
+}
+
+fn error_message(message: impl Into<String>) -> ServerMessage {
+    ServerMessage::Error { message: message.into() }
+}
+
+/// Reads the next message from a client, one JSON object per line; blank
+/// lines are skipped. `Ok(None)` once the client has closed the connection.
+fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if !line.trim().is_empty() {
+            return Ok(Some(line));
+        }
+    }
+}
+
+/// A connected client, with its hello if it sent one.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    hello: Option<Hello>,
+    features: Vec<Feature>, // Agreed on in the welcome
+}
+
+/// A client of a running session, as the session's threads write to it.
+struct SessionClient {
+    stream: TcpStream,
+    legal_moves: bool, // Whether game states include the legal moves
+}
+
+impl Connection {
+    /// Who the client is, for logs and session results.
+    fn identity(&self) -> String {
+        match &self.hello {
+            Some(hello) => hello.identity(),
+            None => match self.reader.get_ref().peer_addr() {
+                Ok(address) => format!("anonymous client at {}", address),
+                Err(_) => "anonymous client".to_string(),
+            },
+        }
+    }
+
+    fn prefers(&self, role: CellType) -> bool {
+        self.hello.as_ref().and_then(|hello| hello.preferred_role) == Some(role)
+    }
+
+    fn session_client(&self) -> SessionClient {
+        SessionClient {
+            stream: self.reader.get_ref().try_clone().expect("Unable to clone client stream"),
+            legal_moves: self.features.contains(&Feature::LegalMoves),
+        }
+    }
+
+    fn close(&self) {
+        let _ = self.reader.get_ref().shutdown(Shutdown::Both);
+    }
+}
+
 fn handle_client(
-    mut stream: TcpStream,
+    mut reader: BufReader<TcpStream>,
     game_state: Arc<Mutex<GameState>>,
-    clients: Arc<Mutex<HashMap<usize, TcpStream>>>,
+    clients: Arc<Mutex<HashMap<usize, SessionClient>>>,
     client_id: usize,
     role: CellType,
     stats: &Arc<Mutex<GameStats>>,
+    turn_changed: &Condvar,
 ) {
-    let mut buffer = [0; 1024];
+    let stream = reader.get_ref().try_clone().expect("Unable to clone client stream");
 
     loop {
         // Start timing before waiting for the move
         let move_start_time = Instant::now();
 
         // Wait for the client's move
-        let size = match stream.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(size) => size,
+        let received_str = match read_line(&mut reader) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
             Err(e) => {
                 eprintln!("Failed to read from client {}: {}", client_id, e);
                 break;
@@ -200,118 +260,179 @@ fn handle_client(
         // Stop timing when the move is received
         let move_duration = move_start_time.elapsed();
 
-        let received_str = String::from_utf8_lossy(&buffer[..size]);
-        match serde_json::from_str::<Move>(&received_str) {
-            Ok(game_move) => {
-                let mut game = game_state.lock().unwrap();
-                if let Err(err) = process_move(&mut game, game_move, role, &clients, stats) {
-                    let error_message = format!("{{\"error\":\"{}\"}}", err);
-                    if let Err(e) = stream.write_all(error_message.as_bytes()) {
-                        eprintln!("Failed to write error to client {}: {}", client_id, e);
-                    }
-                } else {
-                    // Store the move duration in the stats
-                    let mut stats_lock = stats.lock().unwrap();
-                    if role == CellType::Attacker {
-                        stats_lock.move_durations_attacker.push(move_duration);
-                    } else {
-                        stats_lock.move_durations_defender.push(move_duration);
-                    }
-                }
-
-                if let Some(winner) = game.winner {
-                    let mut guard_stats = stats.lock().unwrap();
-                    guard_stats.total_games += 1;
-                    
-                    match winner.cell_type {
-                        CellType::Attacker => {
-                            guard_stats.attacker_wins += 1;
-                            guard_stats.total_attacker_moves += game.attacker_moves;
-                            guard_stats.attacker_moves.push(game.attacker_moves);
-                        },
-                        CellType::Defender => {
-                            guard_stats.defender_wins += 1;
-                            guard_stats.total_defender_moves += game.defender_moves;
-                            guard_stats.defender_moves.push(game.defender_moves);
-                        },
-                        CellType::Empty => guard_stats.ties += 1,
-                        _ => {}
-                    }
-                    game.winner = None;
-
-                    if guard_stats.total_games >= 20 {
-                        println!("All games finished for session {}.", guard_stats.game_id);
-                        println!(
-                            "Average attacker moves per winning game: {:.2}",
-                            guard_stats.total_attacker_moves as f64 / guard_stats.attacker_wins as f64
-                        );
-
-                        println!(
-                            "Average defender moves per winning game: {:.2}",
-                            guard_stats.total_defender_moves as f64 / guard_stats.defender_wins as f64
-                        );
-                        println!(
-                            "Average move duration for attacker: {:?}",
-                            guard_stats.move_durations_attacker.iter().sum::<Duration>() / guard_stats.move_durations_attacker.len() as u32
-                        );
-                        println!(
-                            "Average move duration for defender: {:?}",
-                            guard_stats.move_durations_defender.iter().sum::<Duration>() / guard_stats.move_durations_defender.len() as u32
-                        );
-                        println!("Attacker wins: {}", guard_stats.attacker_wins);
-                        println!("Defender wins: {}", guard_stats.defender_wins);
-                        println!("Ties: {}", guard_stats.ties);
-
-                        let results_file_name = format!("results_session_{}.txt", guard_stats.game_id);
-                        let mut results_file = File::create(&results_file_name).expect("Unable to create results file");
-                        writeln!(results_file, "Session ID: {}", guard_stats.game_id).expect("Unable to write to results file");
-                        writeln!(results_file, "Total games: {}", guard_stats.total_games).expect("Unable to write to results file");
-                        writeln!(results_file, "Average attacker moves per winning game: {:.2}", guard_stats.total_attacker_moves as f64 / guard_stats.total_games as f64).expect("Unable to write to results file");
-                        writeln!(results_file, "Average defender moves per winning game: {:.2}", guard_stats.total_defender_moves as f64 / guard_stats.total_games as f64).expect("Unable to write to results file");
-                        writeln!(results_file, "Average move duration for attacker: {:?}", guard_stats.move_durations_attacker.iter().sum::<Duration>() / guard_stats.move_durations_attacker.len() as u32).expect("Unable to write to results file");
-                        writeln!(results_file, "Average move duration for defender: {:?}", guard_stats.move_durations_defender.iter().sum::<Duration>() / guard_stats.move_durations_defender.len() as u32).expect("Unable to write to results file");
-                        writeln!(results_file, "Attacker wins: {}", guard_stats.attacker_wins).expect("Unable to write to results file");
-                        writeln!(results_file, "Defender wins: {}", guard_stats.defender_wins).expect("Unable to write to results file");
-                        writeln!(results_file, "Ties: {}", guard_stats.ties).expect("Unable to write to results file");
-                        drop(results_file);
-
-                        // Shutdown all client streams
-                        let mut clients_lock = clients.lock().unwrap();
-                        for (id, client_stream) in clients_lock.iter_mut() {
-                            if let Err(e) = client_stream.shutdown(Shutdown::Both) {
-                                eprintln!("Failed to shutdown client {}: {}", id, e);
-                            }
-                        }
-                        clients_lock.clear();
-                        break;
-                    } else {
-                        *game = GameState::new(1);
-                        drop(game);
-                        drop(guard_stats);
-                        initialize_game(&game_state, &clients, &stats);
-                    }
+        let game_move = match ClientMessage::from_line(&received_str) {
+            Ok(ClientMessage::Move(game_move)) => game_move,
+            Ok(ClientMessage::Analyse(options)) => {
+                let current = game_state.lock().unwrap().clone();
+                let response = answer_analysis(&options, Some(&current));
+                if let Err(e) = send_message(&stream, &response) {
+                    eprintln!("Failed to write analysis to client {}: {}", client_id, e);
                 }
+                continue;
+            }
+            Ok(ClientMessage::PlayBot(_)) => {
+                let _ = send_message(&stream, &error_message("A bot can only be asked for before the session starts"));
+                continue;
+            }
+            Ok(ClientMessage::Hello(_)) => {
+                let _ = send_message(&stream, &error_message("Hello is only accepted as the first message"));
+                continue;
             }
             Err(e) => {
-                eprintln!("Failed to deserialize move from client {}: {}", client_id, e);
+                eprintln!("Failed to deserialize message from client {}: {}", client_id, e);
+                let _ = send_message(&stream, &error_message(format!("Invalid message: {}", e)));
+                continue;
+            }
+        };
+
+        let mut game = game_state.lock().unwrap();
+        if let Err(err) = process_move(&mut game, game_move, role, &clients, stats) {
+            if let Err(e) = send_message(&stream, &error_message(err)) {
+                eprintln!("Failed to write error to client {}: {}", client_id, e);
             }
+        } else {
+            // Store the move duration in the stats
+            stats.lock().unwrap().record_move_duration(role, move_duration);
+        }
+
+        turn_changed.notify_all();
+
+        if finish_game(game, &clients, stats) {
+            break;
         }
     }
 
     if let Err(e) = stream.shutdown(Shutdown::Both) {
         eprintln!("Failed to shutdown client {}: {}", client_id, e);
     }
-    clients.lock().unwrap().remove(&client_id);
+    {
+        // Under the game lock, so that a waiting bot opponent sees that no client is left
+        let _game = game_state.lock().unwrap();
+        clients.lock().unwrap().remove(&client_id);
+        turn_changed.notify_all();
+    }
     println!("Client {} disconnected", client_id);
 }
 
-fn initialize_game(
-    game_state: &Arc<Mutex<GameState>>,
-    clients: &Arc<Mutex<HashMap<usize, TcpStream>>>,
+/// If the game in `game` is over, records it and starts the next one, or ends
+/// the session and disconnects the clients after its last game. Returns
+/// whether the session is over.
+fn finish_game(
+    mut game: MutexGuard<GameState>,
+    clients: &Arc<Mutex<HashMap<usize, SessionClient>>>,
     stats: &Arc<Mutex<GameStats>>,
+) -> bool {
+    let Some(winner) = game.winner else {
+        return false;
+    };
+    let mut guard_stats = stats.lock().unwrap();
+    let record = GameRecord {
+        winner: winner.cell_type,
+        attacker_moves: game.attacker_moves,
+        defender_moves: game.defender_moves,
+        seed: guard_stats.seed,
+    };
+    guard_stats.record_game(&record);
+    game.winner = None;
+
+    if guard_stats.total_games >= 20 {
+        println!("All games finished for session {}.", guard_stats.game_id);
+        guard_stats.print_summary();
+
+        let results_file_name = format!("results_session_{}.txt", guard_stats.game_id);
+        guard_stats.write_results(&results_file_name).expect("Unable to write to results file");
+
+        // Shutdown all client streams
+        let mut clients_lock = clients.lock().unwrap();
+        for (id, client) in clients_lock.iter_mut() {
+            if let Err(e) = client.stream.shutdown(Shutdown::Both) {
+                eprintln!("Failed to shutdown client {}: {}", id, e);
+            }
+        }
+        clients_lock.clear();
+        true
+    } else {
+        *game = GameState::new(1);
+        drop(guard_stats);
+        // The next game is set up before the lock is released, so a bot cannot move in between
+        initialize_game(&mut game, clients, stats);
+        false
+    }
+}
+
+/// Plays `role` for a built-in bot, through the same `process_move` as client
+/// moves. Runs until no client is left in the session.
+fn handle_bot(
+    mut agent: Box<dyn Agent<GameState>>,
+    game_state: Arc<Mutex<GameState>>,
+    clients: Arc<Mutex<HashMap<usize, SessionClient>>>,
+    role: CellType,
+    stats: &Arc<Mutex<GameStats>>,
+    turn_changed: &Condvar,
 ) {
-    let mut game = game_state.lock().unwrap();
+    loop {
+        let game = game_state.lock().unwrap();
+        let mut game = turn_changed
+            .wait_while(game, |game| {
+                let waiting = game.winner.is_some() || game.current_turn.cell_type != role;
+                waiting && !clients.lock().unwrap().is_empty()
+            })
+            .unwrap();
+        if clients.lock().unwrap().is_empty() {
+            break;
+        }
+
+        if game.has_legal_move() {
+            // Think without the game lock, so the client can still ask for analysis, move out of turn or leave
+            let position = game.clone();
+            let game_number = stats.lock().unwrap().total_games;
+            drop(game);
+            let move_start_time = Instant::now();
+            let choice = agent.try_choose_move(&position, &Clock::unlimited());
+            let move_duration = move_start_time.elapsed();
+
+            game = game_state.lock().unwrap();
+            if clients.lock().unwrap().is_empty() {
+                break;
+            }
+            // Only play the move in the position it was chosen for
+            let unchanged = game.winner.is_none()
+                && game.current_turn.cell_type == role
+                && game.move_count() == position.move_count()
+                && stats.lock().unwrap().total_games == game_number;
+            if !unchanged {
+                continue;
+            }
+            match choice {
+                Ok(mv) => match process_move(&mut game, mv, role, &clients, stats) {
+                    Ok(()) => stats.lock().unwrap().record_move_duration(role, move_duration),
+                    Err(err) => eprintln!("Bot {} failed to move: {}", agent.name(), err),
+                },
+                Err(err) => {
+                    // A bot that cannot move resigns
+                    eprintln!("Bot {} failed to move: {}", agent.name(), err);
+                    game.set_winner(Some(opponent(role)));
+                }
+            }
+        } else {
+            // A side without any move loses, as in the simulator
+            println!("Bot {} has no legal move", agent.name());
+            game.set_winner(Some(opponent(role)));
+        }
+        turn_changed.notify_all();
 
+        if finish_game(game, &clients, stats) {
+            break;
+        }
+    }
+    println!("Bot {} stopped", agent.name());
+}
+
+fn initialize_game(
+    game: &mut GameState,
+    clients: &Arc<Mutex<HashMap<usize, SessionClient>>>,
+    stats: &Arc<Mutex<GameStats>>,
+) {
     game.current_turn = Cell {
         cell_type: CellType::Attacker,
         is_corner: false,
@@ -323,129 +444,403 @@ fn initialize_game(
     game.defender_moves = 0;
 
     let clients_lock = clients.lock().unwrap();
-    for (id, mut client_stream) in clients_lock.iter() {
-        let start_message = if *id == 1 {
-            "{\"message\":\"Game has started\", \"role\":\"Attacker\"}"
-        } else {
-            "{\"message\":\"Game has started\", \"role\":\"Defender\"}"
-        };
-        if let Err(e) = client_stream.write_all(start_message.as_bytes()) {
+    for (id, client) in clients_lock.iter() {
+        let role = if *id == 1 { CellType::Attacker } else { CellType::Defender };
+        if let Err(e) = send_message(&client.stream, &ServerMessage::GameStarted { role }) {
             eprintln!("Failed to write start message to client {}: {}", id, e);
         }
     }
 
-    let mut board_state = HashMap::new();
-    for (row_idx, row) in game.board.iter().enumerate() {
-        for (col_idx, cell) in row.iter().enumerate() {
-            let key = format!("({}, {})", row_idx, col_idx);
-            board_state.insert(key, cell.cell_type);
-        }
-    }
+    let board_state_json = serde_json::to_string(&BoardState::from_position(&*game)).expect("Unable to serialize board state");
 
-    let response = GameStateResponse {
-        board_state: BoardState { board: board_state },
-        current_turn: game.current_turn.cell_type,
-        winner: None,
-    };
-    
     let file_name = {
         let stats_lock = stats.lock().unwrap();
         format!("results/board_state_session_{}_game_{}.txt", stats_lock.game_id, stats_lock.total_games + 1)
     };
 
     let mut file = File::create(&file_name).expect("Unable to create file");
-    let board_state_json = serde_json::to_string(&response.board_state).expect("Unable to serialize board state");
     file.write_all(board_state_json.as_bytes()).expect("Unable to write data");
     file.write_all(b"\n").expect("Unable to write newline");
     drop(file);
 
-    let response_json = serde_json::to_string(&response).unwrap_or_else(|_| {
-        "{\"error\":\"Failed to serialize game state\"}".to_string()
+    for (id, client) in clients_lock.iter() {
+        if let Err(e) = send_message(&client.stream, &state_message(game, client.legal_moves)) {
+            eprintln!("Failed to write board state to client {}: {}", id, e);
+        }
+    }
+}
+
+/// A bot opponent, with the seed of its random choices if it makes any, so
+/// that its games can be replayed.
+struct Bot {
+    agent: Box<dyn Agent<GameState>>,
+    seed: Option<u64>,
+}
+
+/// Builds the bot asked for in `request`. The search bot plays at the
+/// `strength::Level`; for the others, levels scale the number of MCTS
+/// playouts or an external engine's time per move (250 ms at level 1,
+/// doubling with each level). The random bot has no levels.
+fn create_bot(request: &BotRequest, engines: &EngineCommands) -> Result<Bot, String> {
+    let level = match &request.level {
+        None => DEFAULT_BOT_LEVEL,
+        Some(LevelValue::Number(number)) => Level::from_number(*number)
+            .ok_or(format!("Bot level must be between 1 and {}", Level::ALL.len()))?,
+        Some(LevelValue::Name(name)) => Level::from_name(name).ok_or(format!("Unknown bot level: {}", name))?,
+    };
+    let seed = rng::random_seed();
+    match request.bot.as_str() {
+        "random" => Ok(Bot { agent: Box::new(RandomAgent::new(seed)), seed: Some(seed) }),
+        "search" => Ok(Bot { agent: Box::new(StrengthAgent::new(level, seed)), seed: Some(seed) }),
+        "mcts" => {
+            let playouts = 100 * 3u64.pow(level.number() - 1);
+            let agent = MctsAgent::new(HeuristicRollout, MctsConfig::playouts(playouts), seed);
+            Ok(Bot { agent: Box::new(agent), seed: Some(seed) })
+        }
+        "engine" => {
+            let name = request.name.as_ref().ok_or("Missing engine name")?;
+            let command = engines.get(name).ok_or(format!("Unknown engine: {}", name))?;
+            let limits = SearchLimits::time(Duration::from_millis(250 << (level.number() - 1)));
+            Ok(Bot { agent: Box::new(ExternalAgent::start(command, limits)?), seed: None })
+        }
+        other => Err(format!("Unknown bot: {} (expected random, search, mcts or engine)", other)),
+    }
+}
+
+/// Analyses the position of `options`, or `current` if it names none, and
+/// returns the response (an error message if the request is invalid).
+fn answer_analysis(options: &AnalysisOptions, current: Option<&GameState>) -> ServerMessage {
+    let position = match (&options.position, current) {
+        (Some(text), _) => encoding::position_from_text::<GameState>(text),
+        (None, Some(current)) => Ok(current.clone()),
+        (None, None) => Err("No position to analyse".to_string()),
+    };
+    let position = match position {
+        Ok(position) => position,
+        Err(err) => return error_message(format!("Invalid analysis request: {}", err)),
+    };
+
+    let limits = SearchLimits {
+        max_depth: options.depth.unwrap_or(DEFAULT_ANALYSIS_DEPTH),
+        time: Some(options.time_ms.map_or(MAX_ANALYSIS_TIME, Duration::from_millis).min(MAX_ANALYSIS_TIME)),
+        nodes: None,
+    };
+    let multi_pv = options.multi_pv.unwrap_or(1).min(MAX_ANALYSIS_LINES);
+    ServerMessage::Analysis(analysis::analyse(&position, &limits, multi_pv))
+}
+
+/// Answers analysis requests on a connection that opened with one, until the
+/// client disconnects.
+fn serve_analysis(mut reader: BufReader<TcpStream>, first_request: AnalysisOptions) {
+    let mut request = first_request;
+    loop {
+        let response = answer_analysis(&request, None);
+        if let Err(e) = send_message(reader.get_ref(), &response) {
+            eprintln!("Failed to write analysis: {}", e);
+            break;
+        }
+
+        let line = match read_line(&mut reader) {
+            Ok(Some(line)) => line,
+            Ok(None) | Err(_) => break,
+        };
+        request = match ClientMessage::from_line(&line) {
+            Ok(ClientMessage::Analyse(options)) => options,
+            Ok(_) => {
+                let _ = send_message(reader.get_ref(), &error_message("Only analysis requests are answered here"));
+                break;
+            }
+            Err(e) => {
+                let _ = send_message(reader.get_ref(), &error_message(format!("Invalid analysis request: {}", e)));
+                break;
+            }
+        };
+    }
+    let _ = reader.get_ref().shutdown(Shutdown::Both);
+}
+
+/// Waits up to `BOT_REQUEST_WAIT` for the opening request of a new connection.
+/// `Ok(None)` means the client sent nothing and wants another client as its opponent.
+fn read_opening_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<ClientMessage>> {
+    // Only the start of the message has to arrive in time; the rest of its line is waited for
+    reader.get_ref().set_read_timeout(Some(BOT_REQUEST_WAIT))?;
+    let started = reader.fill_buf().map(|buffer| !buffer.is_empty());
+    reader.get_ref().set_read_timeout(None)?;
+    match started {
+        Ok(true) => {}
+        Ok(false) => return Err(io::ErrorKind::UnexpectedEof.into()),
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let line = read_line(reader)?.ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+    ClientMessage::from_line(&line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Answers a client's hello with the welcome, or with why the client cannot be served.
+fn answer_hello(hello: &Hello) -> Result<Welcome, String> {
+    if hello.protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Unsupported protocol version {}; this server speaks version {}",
+            hello.protocol_version, PROTOCOL_VERSION
+        ));
+    }
+    if !hello.variants.is_empty() && !hello.variants.iter().any(|variant| variant.eq_ignore_ascii_case(GameState::NAME)) {
+        return Err(format!("This server plays {}, which is not among the client's variants", GameState::NAME));
+    }
+    Ok(Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        server: format!("hnefatafl {}", env!("CARGO_PKG_VERSION")),
+        variant: GameState::NAME.to_string(),
+        features: hello.features.iter().filter(|feature| SERVER_FEATURES.contains(feature)).copied().collect(),
+    })
+}
+
+/// Plays a session between two connected clients. The first to connect plays
+/// the attackers, unless the clients' preferred roles say otherwise.
+fn run_session(game_id: u32, first: Connection, second: Connection) {
+    let swap = (first.prefers(CellType::Defender) && !second.prefers(CellType::Defender))
+        || (second.prefers(CellType::Attacker) && !first.prefers(CellType::Attacker));
+    let (client1, client2) = if swap { (second, first) } else { (first, second) };
+
+    let game_state = Arc::new(Mutex::new(GameState::new(1)));
+    let clients = Arc::new(Mutex::new(HashMap::new()));
+    let stats = Arc::new(Mutex::new(GameStats::new(game_id)));
+    let turn_changed = Arc::new(Condvar::new());
+    println!("Session {}: {} (attacker) against {} (defender)", game_id, client1.identity(), client2.identity());
+
+    {
+        let mut stats_lock = stats.lock().unwrap();
+        stats_lock.attacker_player = Some(client1.identity());
+        stats_lock.defender_player = Some(client2.identity());
+        let mut clients_lock = clients.lock().unwrap();
+        clients_lock.insert(1, client1.session_client());
+        clients_lock.insert(2, client2.session_client());
+    }
+
+    let game_state_clone1 = Arc::clone(&game_state);
+    let clients_clone1 = Arc::clone(&clients);
+    let stats_clone1 = Arc::clone(&stats);
+    let turn_changed_clone1 = Arc::clone(&turn_changed);
+    let handle1 = thread::spawn(move || {
+        handle_client(
+            client1.reader,
+            game_state_clone1,
+            clients_clone1,
+            1,
+            CellType::Attacker,
+            &stats_clone1,
+            &turn_changed_clone1,
+        );
     });
 
-    for (id, mut client_stream) in clients_lock.iter() {
-        if let Err(e) = client_stream.write_all(response_json.as_bytes()) {
-            eprintln!("Failed to write board state to client {}: {}", id, e);
+    let game_state_clone2 = Arc::clone(&game_state);
+    let clients_clone2 = Arc::clone(&clients);
+    let stats_clone2 = Arc::clone(&stats);
+    let turn_changed_clone2 = Arc::clone(&turn_changed);
+    let handle2 = thread::spawn(move || {
+        handle_client(
+            client2.reader,
+            game_state_clone2,
+            clients_clone2,
+            2,
+            CellType::Defender,
+            &stats_clone2,
+            &turn_changed_clone2,
+        );
+    });
+
+    initialize_game(&mut game_state.lock().unwrap(), &clients, &stats);
+
+    handle1.join().unwrap();
+    handle2.join().unwrap();
+}
+
+/// Plays a session between a client and a built-in bot. The client keeps the
+/// id of its role (1 attacker, 2 defender), so it gets the usual start message.
+fn run_bot_session(game_id: u32, client: Connection, role: CellType, bot: Bot) {
+    let game_state = Arc::new(Mutex::new(GameState::new(1)));
+    let clients = Arc::new(Mutex::new(HashMap::new()));
+    let stats = Arc::new(Mutex::new(GameStats::new(game_id)));
+    let turn_changed = Arc::new(Condvar::new());
+    let client_id = if role == CellType::Attacker { 1 } else { 2 };
+    let bot_role = opponent(role);
+    println!("Session {}: {} plays {} against bot {}", game_id, client.identity(), role, bot.agent.name());
+    if let Some(seed) = bot.seed {
+        println!("Session {}: bot seed {}", game_id, seed);
+    }
+
+    {
+        let mut stats_lock = stats.lock().unwrap();
+        stats_lock.seed = bot.seed;
+        let (client_player, bot_player) = (Some(client.identity()), Some(format!("bot {}", bot.agent.name())));
+        if role == CellType::Attacker {
+            (stats_lock.attacker_player, stats_lock.defender_player) = (client_player, bot_player);
+        } else {
+            (stats_lock.attacker_player, stats_lock.defender_player) = (bot_player, client_player);
         }
-    }    
+    }
+    clients.lock().unwrap().insert(client_id, client.session_client());
+
+    let game_state_clone = Arc::clone(&game_state);
+    let clients_clone = Arc::clone(&clients);
+    let stats_clone = Arc::clone(&stats);
+    let turn_changed_clone = Arc::clone(&turn_changed);
+    let client_handle = thread::spawn(move || {
+        handle_client(
+            client.reader,
+            game_state_clone,
+            clients_clone,
+            client_id,
+            role,
+            &stats_clone,
+            &turn_changed_clone,
+        );
+    });
+
+    initialize_game(&mut game_state.lock().unwrap(), &clients, &stats);
+
+    // Started after the first game is set up, so the bot never moves before the client has the board
+    let bot_handle = thread::spawn(move || {
+        handle_bot(bot.agent, game_state, clients, bot_role, &stats, &turn_changed);
+    });
+
+    client_handle.join().unwrap();
+    bot_handle.join().unwrap();
+}
+
+/// Greets the new client if it says hello, then starts a bot session or
+/// answers analysis requests if it asks for it, or pairs it with the next
+/// waiting client otherwise.
+fn handle_connection(
+    stream: TcpStream,
+    pending_clients: &Mutex<Vec<Connection>>,
+    game_id_counter: &AtomicU32,
+    engines: &EngineCommands,
+) {
+    // Everything the client sends is read through this one buffer, so nothing read ahead is lost
+    let mut connection = Connection { reader: BufReader::new(stream), hello: None, features: Vec::new() };
+    let request = loop {
+        let request = match read_opening_request(&mut connection.reader) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("Invalid opening message from {}: {}", connection.identity(), e);
+                let _ = send_message(connection.reader.get_ref(), &error_message(format!("Invalid opening request: {}", e)));
+                connection.close();
+                return;
+            }
+        };
+        match request {
+            Some(ClientMessage::Hello(hello)) if connection.hello.is_none() => {
+                let welcome = match answer_hello(&hello) {
+                    Ok(welcome) => welcome,
+                    Err(err) => {
+                        eprintln!("Rejected {}: {}", hello.identity(), err);
+                        let _ = send_message(connection.reader.get_ref(), &error_message(err));
+                        connection.close();
+                        return;
+                    }
+                };
+                println!("{} connected", hello.identity());
+                connection.features = welcome.features.clone();
+                if send_message(connection.reader.get_ref(), &ServerMessage::Welcome(welcome)).is_err() {
+                    connection.close();
+                    return;
+                }
+                // After the welcome the client may still ask for a bot or for analysis
+                connection.hello = Some(hello);
+            }
+            Some(ClientMessage::Hello(_)) => {
+                let _ = send_message(connection.reader.get_ref(), &error_message("Hello was already sent"));
+                connection.close();
+                return;
+            }
+            request => break request,
+        }
+    };
+
+    if let Some(ClientMessage::Analyse(options)) = request {
+        serve_analysis(connection.reader, options);
+        return;
+    }
+    if let Some(ClientMessage::Move(_)) = request {
+        let _ = send_message(connection.reader.get_ref(), &error_message("No game has started yet"));
+        connection.close();
+        return;
+    }
+    if let Some(ClientMessage::PlayBot(request)) = request {
+        let bot = match create_bot(&request, engines) {
+            Ok(bot) => bot,
+            Err(err) => {
+                let _ = send_message(connection.reader.get_ref(), &error_message(err));
+                connection.close();
+                return;
+            }
+        };
+        let preferred_role = connection.hello.as_ref().and_then(|hello| hello.preferred_role);
+        let role = match request.role.or(preferred_role) {
+            Some(CellType::Defender) | Some(CellType::King) => CellType::Defender,
+            _ => CellType::Attacker,
+        };
+        let game_id = game_id_counter.fetch_add(1, Ordering::Relaxed);
+        run_bot_session(game_id, connection, role, bot);
+        return;
+    }
+
+    let pair = {
+        let mut pending = pending_clients.lock().unwrap();
+        pending.push(connection);
+        if pending.len() >= 2 {
+            Some((pending.remove(0), pending.remove(0)))
+        } else {
+            None
+        }
+    };
+    if let Some((first, second)) = pair {
+        let game_id = game_id_counter.fetch_add(1, Ordering::Relaxed);
+        run_session(game_id, first, second);
+    }
+}
+
+fn parse_args() -> Result<EngineCommands, String> {
+    let mut engines = EngineCommands::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--engine" => {
+                let (name, command) = value.split_once('=').ok_or(format!("Expected NAME=COMMAND: {}", value))?;
+                engines.insert(name.to_string(), command.to_string());
+            }
+            _ => return Err(format!("Unknown option: {}", flag)),
+        }
+    }
+    Ok(engines)
 }
 
 fn main() -> io::Result<()> {
+    let engines = match parse_args() {
+        Ok(engines) => Arc::new(engines),
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
     let listener = TcpListener::bind("127.0.0.1:7878")?;
-    let mut pending_clients: Vec<TcpStream> = Vec::new();
-    let mut game_id_counter = 1;
+    let pending_clients: Arc<Mutex<Vec<Connection>>> = Arc::new(Mutex::new(Vec::new()));
+    let game_id_counter = Arc::new(AtomicU32::new(1));
 
     println!("Server listening on port 7878");
 
     for stream in listener.incoming() {
         let stream = stream?;
-        pending_clients.push(stream);
-
-        if pending_clients.len() >= 2 {
-            let client1 = pending_clients.remove(0);
-            let client2 = pending_clients.remove(0);
-            let game_id = game_id_counter;
-            game_id_counter += 1;
-
-            thread::spawn(move || {
-                let game_state = Arc::new(Mutex::new(GameState::new(1)));
-                let clients = Arc::new(Mutex::new(HashMap::new()));
-                let stats = Arc::new(Mutex::new(GameStats {
-                    game_id,
-                    total_games: 0,
-                    total_attacker_moves: 0,
-                    total_defender_moves: 0,
-                    attacker_wins: 0,
-                    defender_wins: 0,
-                    ties: 0,
-                    move_durations_attacker: Vec::new(),
-                    move_durations_defender: Vec::new(),
-                    attacker_moves: Vec::new(),
-                    defender_moves: Vec::new(),
-                }));
-
-                {
-                    let mut clients_lock = clients.lock().unwrap();
-                    clients_lock.insert(1, client1.try_clone().unwrap());
-                    clients_lock.insert(2, client2.try_clone().unwrap());
-                }
-
-                let game_state_clone1 = Arc::clone(&game_state);
-                let clients_clone1 = Arc::clone(&clients);
-                let stats_clone1 = Arc::clone(&stats);
-                let handle1 = thread::spawn(move || {
-                    handle_client(
-                        client1,
-                        game_state_clone1,
-                        clients_clone1,
-                        1,
-                        CellType::Attacker,
-                        &stats_clone1,
-                    );
-                });
-
-                let game_state_clone2 = Arc::clone(&game_state);
-                let clients_clone2 = Arc::clone(&clients);
-                let stats_clone2 = Arc::clone(&stats);
-                let handle2 = thread::spawn(move || {
-                    handle_client(
-                        client2,
-                        game_state_clone2,
-                        clients_clone2,
-                        2,
-                        CellType::Defender,
-                        &stats_clone2,
-                    );
-                });
-
-                initialize_game(&game_state, &clients, &stats);
-
-                handle1.join().unwrap();
-                handle2.join().unwrap();
-            });
-        }
+        let pending_clients = Arc::clone(&pending_clients);
+        let game_id_counter = Arc::clone(&game_id_counter);
+        let engines = Arc::clone(&engines);
+        thread::spawn(move || handle_connection(stream, &pending_clients, &game_id_counter, &engines));
     }
 
     Ok(())
 
-}
\ No newline at end of file
+}
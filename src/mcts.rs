@@ -0,0 +1,426 @@
+//! Monte Carlo Tree Search (UCT) over the rules engine.
+//!
+//! Each playout walks down the tree picking the child with the best UCT
+//! score, adds one new node, finishes the game with a rollout policy and
+//! credits the result to every node on the way back up. The move played is
+//! the most visited child of the root.
+
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::agent::{Agent, Clock};
+use crate::cell::CellType;
+use crate::rng::{self, GameRng};
+use crate::variant::{opponent, Move, Variant};
+
+/// Playouts per move when neither a playout count nor a time budget is set.
+pub const DEFAULT_PLAYOUTS: u64 = 10_000;
+
+/// Picks the moves of a playout once it leaves the tree.
+pub trait RolloutPolicy<G: Variant>: Send {
+    /// Picks one of `moves`, the (non-empty) legal moves of `position`.
+    fn choose(&self, position: &G, moves: &[Move], rng: &mut GameRng) -> Move;
+}
+
+/// Uniformly random moves: the fastest rollouts, and the least informed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomRollout;
+
+impl<G: Variant> RolloutPolicy<G> for RandomRollout {
+    fn choose(&self, _position: &G, moves: &[Move], rng: &mut GameRng) -> Move {
+        moves[rng.random_range(0..moves.len())]
+    }
+}
+
+/// A random capturing move if there is one, otherwise a random move.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureGreedyRollout;
+
+impl<G: Variant> RolloutPolicy<G> for CaptureGreedyRollout {
+    fn choose(&self, position: &G, moves: &[Move], rng: &mut GameRng) -> Move {
+        let captures: Vec<Move> = moves.iter().copied().filter(|&mv| position.is_capture(mv)).collect();
+        let choices = if captures.is_empty() { moves } else { &captures };
+        choices[rng.random_range(0..choices.len())]
+    }
+}
+
+/// Random moves weighted towards what usually matters in tafl: the king
+/// escaping to a corner (always taken), captures, king moves for the
+/// defenders and closing in on the king for the attackers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicRollout;
+
+impl<G: Variant> RolloutPolicy<G> for HeuristicRollout {
+    fn choose(&self, position: &G, moves: &[Move], rng: &mut GameRng) -> Move {
+        let king = position.king_position();
+        let weights: Vec<u32> = moves
+            .iter()
+            .map(|&mv| {
+                let is_king = Some(mv.from) == king;
+                if is_king && position.cell(mv.to).is_corner {
+                    return u32::MAX / 2; // A winning move, play it
+                }
+                let mut weight = 1 + 8 * position.captured_by(mv).len() as u32;
+                if is_king {
+                    weight += 2;
+                } else if let Some(king) = king.filter(|_| position.side_to_move() == CellType::Attacker) {
+                    if mv.to.0.abs_diff(king.0) + mv.to.1.abs_diff(king.1) == 1 {
+                        weight += 3;
+                    }
+                }
+                weight
+            })
+            .collect();
+
+        if let Some(index) = weights.iter().position(|&weight| weight == u32::MAX / 2) {
+            return moves[index];
+        }
+        let total: u64 = weights.iter().map(|&weight| weight as u64).sum();
+        let mut pick = rng.random_range(0..total);
+        for (&mv, &weight) in moves.iter().zip(&weights) {
+            if pick < weight as u64 {
+                return mv;
+            }
+            pick -= weight as u64;
+        }
+        moves[moves.len() - 1]
+    }
+}
+
+/// Search settings. The search stops at whichever of `playouts` and `time`
+/// (capped by the clock) is reached first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MctsConfig {
+    pub playouts: Option<u64>,
+    pub time: Option<Duration>,
+    pub exploration: f64, // UCT exploration constant; higher tries more moves
+    pub reuse_tree: bool, // Keep the subtree of the actual game between moves
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig {
+            playouts: Some(DEFAULT_PLAYOUTS),
+            time: None,
+            exploration: std::f64::consts::SQRT_2,
+            reuse_tree: true,
+        }
+    }
+}
+
+impl MctsConfig {
+    pub fn playouts(playouts: u64) -> Self {
+        MctsConfig {
+            playouts: Some(playouts),
+            ..Default::default()
+        }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        MctsConfig {
+            playouts: None,
+            time: Some(time),
+            ..Default::default()
+        }
+    }
+}
+
+/// Result of the last search.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MctsInfo {
+    pub best_move: Option<Move>,
+    pub playouts: u64,    // Playouts run for this move
+    pub visits: u32,      // Visits of the root, including playouts reused from earlier moves
+    pub win_rate: f64,    // Share of `best_move`'s playouts won by the side to move (draws count half)
+    pub tree_size: usize, // Nodes in the tree
+    pub elapsed: Duration,
+    pub root_visits: Vec<(Move, u32)>, // Visits of every expanded root move
+}
+
+struct Node {
+    mv: Option<Move>, // Move leading to this node, `None` at the root
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<Move>,       // Legal moves without a child node yet
+    mover: CellType,          // Side that played `mv`
+    result: Option<CellType>, // Winner (`Empty` for a draw) if the game is over here
+    visits: u32,
+    reward: f64, // Sum of playout results for `mover`: 1 per win, 0.5 per draw
+}
+
+impl Node {
+    fn new<G: Variant>(position: &G, mv: Option<Move>, parent: Option<usize>, mover: CellType) -> Self {
+        let (result, untried) = match position.outcome() {
+            Some(result) => (Some(result), Vec::new()),
+            None => {
+                let moves = position.legal_moves();
+                if moves.is_empty() {
+                    (Some(opponent(position.side_to_move())), moves) // A side that cannot move loses
+                } else {
+                    (None, moves)
+                }
+            }
+        };
+        Node {
+            mv,
+            parent,
+            children: Vec::new(),
+            untried,
+            mover,
+            result,
+            visits: 0,
+            reward: 0.0,
+        }
+    }
+
+    /// Stands in for a node moved out of the tree while rerooting.
+    fn placeholder() -> Self {
+        Node {
+            mv: None,
+            parent: None,
+            children: Vec::new(),
+            untried: Vec::new(),
+            mover: CellType::Empty,
+            result: None,
+            visits: 0,
+            reward: 0.0,
+        }
+    }
+}
+
+/// Agent choosing its moves by UCT search with a pluggable rollout policy.
+pub struct MctsAgent<G: Variant, P: RolloutPolicy<G>> {
+    policy: P,
+    config: MctsConfig,
+    seed: u64,
+    rng: GameRng,
+    nodes: Vec<Node>,         // The tree, root first
+    root_position: Option<G>, // Position of `nodes[0]`
+    last_info: MctsInfo,
+}
+
+impl<G: Variant, P: RolloutPolicy<G>> MctsAgent<G, P> {
+    pub fn new(policy: P, config: MctsConfig, seed: u64) -> Self {
+        MctsAgent {
+            policy,
+            config,
+            seed,
+            rng: rng::rng_from_seed(seed),
+            nodes: Vec::new(),
+            root_position: None,
+            last_info: MctsInfo::default(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn last_info(&self) -> &MctsInfo {
+        &self.last_info
+    }
+
+    /// Runs playouts from `position` and returns the most visited move, or
+    /// `None` if the side to move has no legal move.
+    pub fn search(&mut self, position: &G, time: Option<Duration>) -> Option<Move> {
+        let start = Instant::now();
+        let deadline = time.map(|time| start + time);
+        let playout_limit = match (self.config.playouts, deadline) {
+            (None, None) => Some(DEFAULT_PLAYOUTS),
+            (playouts, _) => playouts,
+        };
+
+        self.set_root(position);
+        let mut playouts = 0;
+        while self.nodes[0].result.is_none()
+            && playout_limit.is_none_or(|limit| playouts < limit)
+            && deadline.is_none_or(|deadline| playouts == 0 || Instant::now() < deadline)
+        {
+            self.playout();
+            playouts += 1;
+        }
+
+        let best = self.nodes[0]
+            .children
+            .iter()
+            .copied()
+            .max_by(|&a, &b| {
+                let (a, b) = (&self.nodes[a], &self.nodes[b]);
+                a.visits.cmp(&b.visits).then(a.reward.total_cmp(&b.reward))
+            });
+        self.last_info = MctsInfo {
+            best_move: best.and_then(|index| self.nodes[index].mv),
+            playouts,
+            visits: self.nodes[0].visits,
+            win_rate: best.map_or(0.0, |index| {
+                let node = &self.nodes[index];
+                node.reward / node.visits.max(1) as f64
+            }),
+            tree_size: self.nodes.len(),
+            elapsed: start.elapsed(),
+            root_visits: self.nodes[0]
+                .children
+                .iter()
+                .filter_map(|&index| Some((self.nodes[index].mv?, self.nodes[index].visits)))
+                .collect(),
+        };
+        self.last_info.best_move
+    }
+
+    /// Makes `position` the root, keeping the matching subtree of the previous
+    /// search (our move and the opponent's reply) when tree reuse is on.
+    fn set_root(&mut self, position: &G) {
+        let reused = if self.config.reuse_tree { self.find_node(position) } else { None };
+        match reused {
+            Some(index) => self.reroot(index),
+            None => {
+                let mover = opponent(position.side_to_move());
+                self.nodes = vec![Node::new(position, None, None, mover)];
+            }
+        }
+        self.root_position = Some(position.clone());
+    }
+
+    /// A node at most two plies below the root whose position is `position`.
+    fn find_node(&self, position: &G) -> Option<usize> {
+        let root_position = self.root_position.as_ref()?;
+        let key = position.position_key();
+        let matches = |candidate: &G| {
+            candidate.move_count() == position.move_count() && candidate.position_key() == key
+        };
+        if matches(root_position) {
+            return Some(0);
+        }
+        for &child in &self.nodes[0].children {
+            let mut child_position = root_position.clone();
+            child_position.apply_move(self.nodes[child].mv?).ok()?;
+            for &grandchild in &self.nodes[child].children {
+                let mut grandchild_position = child_position.clone();
+                grandchild_position.apply_move(self.nodes[grandchild].mv?).ok()?;
+                if matches(&grandchild_position) {
+                    return Some(grandchild);
+                }
+            }
+        }
+        None
+    }
+
+    /// Keeps only the subtree under `index`, which becomes the root.
+    fn reroot(&mut self, index: usize) {
+        if index == 0 {
+            return;
+        }
+        let mut old = std::mem::take(&mut self.nodes);
+        let mut queue = vec![(index, None)];
+        while let Some((old_index, parent)) = queue.pop() {
+            let new_index = self.nodes.len();
+            let mut node = std::mem::replace(&mut old[old_index], Node::placeholder());
+            let children = std::mem::take(&mut node.children);
+            node.parent = parent;
+            self.nodes.push(node);
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push(new_index);
+            }
+            queue.extend(children.into_iter().rev().map(|child| (child, Some(new_index))));
+        }
+        self.nodes[0].mv = None;
+    }
+
+    fn playout(&mut self) {
+        let mut position = self.root_position.clone().expect("search sets the root");
+        let mut node = 0;
+
+        // Selection and expansion
+        while self.nodes[node].result.is_none() {
+            if !self.nodes[node].untried.is_empty() {
+                let untried = &mut self.nodes[node].untried;
+                let mv = untried.swap_remove(self.rng.random_range(0..untried.len()));
+                let mover = position.side_to_move();
+                if position.apply_move(mv).is_err() {
+                    continue;
+                }
+                let child = self.nodes.len();
+                self.nodes.push(Node::new(&position, Some(mv), Some(node), mover));
+                self.nodes[node].children.push(child);
+                node = child;
+                break;
+            }
+            node = self.select_child(node);
+            let mv = self.nodes[node].mv.expect("only the root has no move");
+            if position.apply_move(mv).is_err() {
+                break;
+            }
+        }
+
+        let result = match self.nodes[node].result {
+            Some(result) => result,
+            None => self.rollout(position),
+        };
+
+        // Backpropagation
+        let mut current = Some(node);
+        while let Some(index) = current {
+            let node = &mut self.nodes[index];
+            node.visits += 1;
+            node.reward += match result {
+                CellType::Empty => 0.5,
+                winner if winner == node.mover => 1.0,
+                _ => 0.0,
+            };
+            current = node.parent;
+        }
+    }
+
+    fn select_child(&self, node: usize) -> usize {
+        let log_visits = (self.nodes[node].visits.max(1) as f64).ln();
+        let uct = |child: usize| {
+            let child = &self.nodes[child];
+            let visits = child.visits.max(1) as f64;
+            child.reward / visits + self.config.exploration * (log_visits / visits).sqrt()
+        };
+        self.nodes[node]
+            .children
+            .iter()
+            .copied()
+            .max_by(|&a, &b| uct(a).total_cmp(&uct(b)))
+            .expect("a node without untried moves or a result has children")
+    }
+
+    /// Plays `position` out with the rollout policy and returns the winner,
+    /// `CellType::Empty` for a draw.
+    fn rollout(&mut self, mut position: G) -> CellType {
+        loop {
+            if let Some(result) = position.outcome() {
+                return result;
+            }
+            let moves = position.legal_moves();
+            if moves.is_empty() {
+                return opponent(position.side_to_move());
+            }
+            let mv = self.policy.choose(&position, &moves, &mut self.rng);
+            if position.apply_move(mv).is_err() {
+                return opponent(position.side_to_move()); // An illegal move loses, as in a real game
+            }
+        }
+    }
+}
+
+impl<G: Variant, P: RolloutPolicy<G>> Agent<G> for MctsAgent<G, P> {
+    fn name(&self) -> String {
+        "mcts".to_string()
+    }
+
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move {
+        let time = match (self.config.time, clock.budget()) {
+            (Some(own), Some(budget)) => Some(own.min(budget)),
+            (own, budget) => own.or(budget),
+        };
+        self.search(position, time)
+            .unwrap_or_else(|| position.legal_moves()[0])
+    }
+
+    fn move_weights(&self) -> Option<Vec<(Move, u32)>> {
+        Some(self.last_info.root_visits.clone())
+    }
+}
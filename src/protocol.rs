@@ -0,0 +1,361 @@
+//! Messages of the TCP protocol between the server and its clients.
+//!
+//! Every message is a JSON object on a line of its own, with a `type` field
+//! naming its kind, e.g. `{"type":"move","from":[6,3],"to":[6,1]}`. The server
+//! and Rust clients share these types, so both sides always agree on the
+//! format; `to_line` and `from_line` convert a message to and from one line.
+//!
+//! A client introduces itself with a `hello` as its first message, giving the
+//! protocol version it speaks, and the server answers with a `welcome`, or an
+//! `error` and closes the connection if it cannot serve the client. Clients
+//! that send no `hello` are served as version 1 clients without a name.
+
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::Analysis;
+use crate::cell::CellType;
+use crate::variant::{Move, Variant};
+
+/// Version of the protocol described here.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A message from a client to the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Introduces the client, as the first message of a connection.
+    Hello(Hello),
+    /// A move in the client's game.
+    Move(Move),
+    /// As the first message of a connection: play against a built-in bot
+    /// instead of waiting for a second client.
+    PlayBot(BotRequest),
+    /// Analyse a position. As the first message of a connection, the
+    /// connection then only answers analysis requests.
+    Analyse(AnalysisOptions),
+}
+
+impl ClientMessage {
+    /// Parses one line from a client. A move without a `type` field, as sent
+    /// by clients written before messages were tagged, is still a `Move`.
+    pub fn from_line(line: &str) -> Result<ClientMessage, String> {
+        let value: serde_json::Value = from_line(line)?;
+        if value.get("type").is_none() {
+            if let Ok(mv) = Move::deserialize(&value) {
+                return Ok(ClientMessage::Move(mv));
+            }
+        }
+        ClientMessage::deserialize(&value).map_err(|e| e.to_string())
+    }
+}
+
+/// A message from the server to a client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The answer to an accepted `Hello`.
+    Welcome(Welcome),
+    /// A new game of the session starts, with the client playing `role`.
+    GameStarted { role: CellType },
+    /// The position after every move, and at the start of each game.
+    GameState {
+        board_state: BoardState,
+        current_turn: CellType,
+        winner: Option<CellType>, // `CellType::Empty` for a draw
+        // With the `legal_moves` feature, the moves of the side to move; empty once the game is over
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        legal_moves: Option<Vec<Move>>,
+    },
+    /// The answer to an `Analyse` request.
+    Analysis(Analysis),
+    /// A request could not be served; the connection stays open unless it
+    /// was the first message.
+    Error { message: String },
+}
+
+/// Who a client is and what it supports, e.g.
+/// `{"type":"hello","protocol_version":1,"name":"my-bot","version":"0.3","variants":["brandubh"],"preferred_role":"Defender","features":["legal_moves"]}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub name: String, // Name of the client or bot
+    pub version: Option<String>,
+    #[serde(default)]
+    pub variants: Vec<String>, // Variants the client can play, by name; any if empty
+    pub preferred_role: Option<CellType>,
+    #[serde(default)]
+    pub features: Vec<Feature>, // Optional features the client would like to use
+}
+
+impl Hello {
+    /// A hello for the current `PROTOCOL_VERSION`, with no preferences.
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            name: name.into(),
+            version: Some(version.into()),
+            variants: Vec::new(),
+            preferred_role: None,
+            features: Vec::new(),
+        }
+    }
+
+    /// Name and version, for logs and results.
+    pub fn identity(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{} {}", self.name, version),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// The server's answer to a `Hello`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Welcome {
+    pub protocol_version: u32,
+    pub server: String,         // Name and version of the server
+    pub variant: String,        // The variant the server plays
+    pub features: Vec<Feature>, // The client's requested features that the server supports
+}
+
+/// Optional parts of the protocol, agreed on in the hello exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    Clocks,     // Time left for each side
+    LegalMoves, // Legal moves of the side to move in every game state
+    Chat,       // Messages between the players
+    #[serde(other)]
+    Unknown, // A feature from another version of the protocol
+}
+
+/// Asks for a built-in bot opponent, e.g.
+/// `{"type":"play_bot","bot":"search","level":"easy","role":"Defender"}`, or for
+/// an external engine registered on the server, e.g.
+/// `{"type":"play_bot","bot":"engine","name":"NAME"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BotRequest {
+    pub bot: String,               // "random", "search", "mcts" or "engine"
+    pub name: Option<String>,      // Name of the external engine, for "engine" only
+    pub level: Option<LevelValue>, // The server's default level if absent
+    pub role: Option<CellType>,    // The client's role, Attacker if absent
+}
+
+/// A bot level, by number (1 weakest to 5) or by the name of a `strength::Level`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LevelValue {
+    Number(u32),
+    Name(String),
+}
+
+/// What to analyse, e.g.
+/// `{"type":"analyse","position":"...A.../.../...A... a","depth":5,"multi_pv":3}`,
+/// with the position in the text notation of `encoding`. Within a session the
+/// position may be left out to analyse the current game.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalysisOptions {
+    pub position: Option<String>,
+    pub depth: Option<u32>,
+    pub time_ms: Option<u64>, // Per line; the server caps it
+    pub multi_pv: Option<usize>,
+}
+
+/// The pieces on the board by square, keyed `"(row, col)"`. Every square is
+/// present, empty ones as `CellType::Empty`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoardState {
+    pub board: HashMap<String, CellType>,
+}
+
+impl BoardState {
+    pub fn from_position<G: Variant>(position: &G) -> Self {
+        let mut board = HashMap::new();
+        for row in 0..G::SIZE {
+            for col in 0..G::SIZE {
+                board.insert(format!("({}, {})", row, col), position.cell((row, col)).cell_type);
+            }
+        }
+        BoardState { board }
+    }
+
+    /// The position with these pieces and `side_to_move` to move.
+    pub fn to_position<G: Variant>(&self, side_to_move: CellType) -> Result<G, String> {
+        G::from_board_map(&self.board, side_to_move)
+    }
+}
+
+/// `message` as one line of the protocol, newline included.
+pub fn to_line<T: Serialize>(message: &T) -> String {
+    let mut line = serde_json::to_string(message).expect("Protocol messages always serialize");
+    line.push('\n');
+    line
+}
+
+/// Parses one line of the protocol; surrounding whitespace is ignored.
+pub fn from_line<T: DeserializeOwned>(line: &str) -> Result<T, String> {
+    serde_json::from_str(line.trim()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(from: (usize, usize), to: (usize, usize)) -> Move {
+        Move { from, to }
+    }
+
+    /// `message` is written as exactly `line` and read back from it.
+    fn assert_wire_format<T>(message: T, line: &str)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(to_line(&message), format!("{}\n", line));
+        assert_eq!(from_line::<T>(line), Ok(message));
+    }
+
+    #[test]
+    fn client_messages() {
+        assert_wire_format(
+            ClientMessage::Move(mv((6, 3), (6, 1))),
+            r#"{"type":"move","from":[6,3],"to":[6,1]}"#,
+        );
+        assert_wire_format(
+            ClientMessage::PlayBot(BotRequest {
+                bot: "search".to_string(),
+                name: None,
+                level: Some(LevelValue::Name("easy".to_string())),
+                role: Some(CellType::Defender),
+            }),
+            r#"{"type":"play_bot","bot":"search","name":null,"level":"easy","role":"Defender"}"#,
+        );
+        assert_wire_format(
+            ClientMessage::Analyse(AnalysisOptions {
+                position: Some("...A.../...A.../...D.../AADKDAA/...D.../...A.../...A... a".to_string()),
+                depth: Some(5),
+                time_ms: None,
+                multi_pv: Some(3),
+            }),
+            r#"{"type":"analyse","position":"...A.../...A.../...D.../AADKDAA/...D.../...A.../...A... a","depth":5,"time_ms":null,"multi_pv":3}"#,
+        );
+        assert_wire_format(
+            ClientMessage::Hello(Hello {
+                variants: vec!["brandubh".to_string()],
+                preferred_role: Some(CellType::Defender),
+                features: vec![Feature::LegalMoves],
+                ..Hello::new("my-bot", "0.3")
+            }),
+            r#"{"type":"hello","protocol_version":1,"name":"my-bot","version":"0.3","variants":["brandubh"],"preferred_role":"Defender","features":["legal_moves"]}"#,
+        );
+    }
+
+    #[test]
+    fn optional_client_fields_may_be_left_out() {
+        assert_eq!(
+            ClientMessage::from_line(r#"{"type":"play_bot","bot":"engine","name":"NAME","level":3}"#),
+            Ok(ClientMessage::PlayBot(BotRequest {
+                bot: "engine".to_string(),
+                name: Some("NAME".to_string()),
+                level: Some(LevelValue::Number(3)),
+                role: None,
+            }))
+        );
+        assert_eq!(
+            ClientMessage::from_line(r#"{"type":"hello","protocol_version":1,"name":"x"}"#),
+            Ok(ClientMessage::Hello(Hello {
+                version: None,
+                ..Hello::new("x", "")
+            }))
+        );
+        assert_eq!(
+            ClientMessage::from_line(r#"{"type":"analyse"}"#),
+            Ok(ClientMessage::Analyse(AnalysisOptions::default()))
+        );
+    }
+
+    #[test]
+    fn untagged_moves_are_still_accepted() {
+        assert_eq!(
+            ClientMessage::from_line("{\"from\":[0,3],\"to\":[0,1]}\n"),
+            Ok(ClientMessage::Move(mv((0, 3), (0, 1))))
+        );
+        // Only moves may leave out the type
+        assert!(ClientMessage::from_line(r#"{"bot":"random"}"#).is_err());
+        assert!(ClientMessage::from_line(r#"{"type":"teleport","from":[0,3],"to":[0,1]}"#).is_err());
+        assert!(ClientMessage::from_line("not json").is_err());
+    }
+
+    #[test]
+    fn unknown_features_are_kept_apart() {
+        let hello = ClientMessage::from_line(
+            r#"{"type":"hello","protocol_version":1,"name":"x","features":["legal_moves","teleport","chat"]}"#,
+        );
+        let Ok(ClientMessage::Hello(hello)) = hello else {
+            panic!("Expected a hello, got {:?}", hello);
+        };
+        assert_eq!(hello.features, vec![Feature::LegalMoves, Feature::Unknown, Feature::Chat]);
+    }
+
+    #[test]
+    fn server_messages() {
+        assert_wire_format(
+            ServerMessage::Welcome(Welcome {
+                protocol_version: PROTOCOL_VERSION,
+                server: "hnefatafl 0.1.0".to_string(),
+                variant: "Brandubh".to_string(),
+                features: vec![Feature::LegalMoves],
+            }),
+            r#"{"type":"welcome","protocol_version":1,"server":"hnefatafl 0.1.0","variant":"Brandubh","features":["legal_moves"]}"#,
+        );
+        assert_wire_format(
+            ServerMessage::GameStarted { role: CellType::Attacker },
+            r#"{"type":"game_started","role":"Attacker"}"#,
+        );
+        assert_wire_format(
+            ServerMessage::Error { message: "No game has started yet".to_string() },
+            r#"{"type":"error","message":"No game has started yet"}"#,
+        );
+
+        let analysis = ServerMessage::Analysis(Analysis {
+            side: Some(CellType::Defender),
+            best_move: Some(mv((3, 3), (3, 0))),
+            score: 12,
+            pv: vec![mv((3, 3), (3, 0))],
+            ..Analysis::default()
+        });
+        let line = to_line(&analysis);
+        assert!(line.starts_with(r#"{"type":"analysis","side":"Defender","best_move":{"from":[3,3],"to":[3,0]},"score":12,"#));
+        assert_eq!(from_line::<ServerMessage>(&line), Ok(analysis));
+    }
+
+    #[test]
+    fn legal_moves_are_only_sent_when_asked_for() {
+        let board = HashMap::from([("(0, 3)".to_string(), CellType::King)]);
+        let state = |legal_moves| ServerMessage::GameState {
+            board_state: BoardState { board: board.clone() },
+            current_turn: CellType::Defender,
+            winner: None,
+            legal_moves,
+        };
+        assert_wire_format(
+            state(None),
+            r#"{"type":"game_state","board_state":{"board":{"(0, 3)":"King"}},"current_turn":"Defender","winner":null}"#,
+        );
+        assert_wire_format(
+            state(Some(vec![mv((0, 3), (0, 0))])),
+            r#"{"type":"game_state","board_state":{"board":{"(0, 3)":"King"}},"current_turn":"Defender","winner":null,"legal_moves":[{"from":[0,3],"to":[0,0]}]}"#,
+        );
+        assert_wire_format(
+            ServerMessage::GameState {
+                board_state: BoardState::default(),
+                current_turn: CellType::Attacker,
+                winner: Some(CellType::Empty),
+                legal_moves: Some(Vec::new()),
+            },
+            r#"{"type":"game_state","board_state":{"board":{}},"current_turn":"Attacker","winner":"Empty","legal_moves":[]}"#,
+        );
+    }
+}
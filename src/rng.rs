@@ -0,0 +1,30 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Random number generator used by every built-in random component.
+///
+/// The ChaCha8 output stream is fixed by its specification, so the same seed
+/// replays the same game on any platform and with any version of `rand`.
+pub type GameRng = ChaCha8Rng;
+
+pub fn rng_from_seed(seed: u64) -> GameRng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// Derives an independent seed for sub-stream `stream` of `seed`, such as one
+/// game of a simulation run or one player of a game.
+pub fn derive_seed(seed: u64, stream: u64) -> u64 {
+    splitmix64(seed ^ splitmix64(stream))
+}
+
+/// A fresh seed from the operating system, for runs where none was given.
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,565 @@
+//! Alpha-beta (negamax) search with iterative deepening.
+//!
+//! Scores are in centipawn-like units from the point of view of the side the
+//! search is run for; wins are reported as `WIN_SCORE` minus the number of
+//! plies to the win, so shorter wins score higher.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::agent::{Agent, Clock};
+use crate::cell::CellType;
+use crate::tt::{self, Bound, TranspositionTable, TtEntry};
+use crate::variant::{opponent, Move, Variant};
+
+pub const WIN_SCORE: i32 = 1_000_000;
+const INFINITY: i32 = WIN_SCORE + 1;
+const MAX_PLY: usize = 128;
+
+/// Scores beyond this are wins or losses found by the search.
+pub const WIN_THRESHOLD: i32 = WIN_SCORE - MAX_PLY as i32;
+
+/// Static evaluation of a position, used at the leaves of the search.
+pub trait Evaluator<G: Variant>: Send + Sync {
+    /// Score of `position` for `side`; positive is good for `side`.
+    fn evaluate(&self, position: &G, side: CellType) -> i32;
+}
+
+/// Material and king distance to the nearest corner. A deliberately simple
+/// default; any `Evaluator` can be plugged into the engine instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleEvaluator;
+
+impl<G: Variant> Evaluator<G> for SimpleEvaluator {
+    fn evaluate(&self, position: &G, side: CellType) -> i32 {
+        let attackers = position.count_pieces(CellType::Attacker) as i32;
+        let defenders = position.count_pieces(CellType::Defender) as i32;
+        let mut king_distance = 0;
+        for row in 0..G::SIZE {
+            for col in 0..G::SIZE {
+                if position.cell((row, col)).cell_type == CellType::King {
+                    let last = G::SIZE - 1;
+                    king_distance = (row.min(last - row) + col.min(last - col)) as i32;
+                }
+            }
+        }
+        let attacker_score = 100 * attackers - 200 * defenders + 25 * king_distance;
+        if side == CellType::Attacker { attacker_score } else { -attacker_score }
+    }
+}
+
+/// When to stop searching. The search always completes depth 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub max_depth: u32,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            max_depth: 64,
+            time: None,
+            nodes: None,
+        }
+    }
+}
+
+impl SearchLimits {
+    pub fn depth(max_depth: u32) -> Self {
+        SearchLimits {
+            max_depth,
+            ..Default::default()
+        }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        SearchLimits {
+            time: Some(time),
+            ..Default::default()
+        }
+    }
+}
+
+/// Result of a search (or of one completed iteration of it).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchInfo {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>, // Principal variation, starting with `best_move`
+}
+
+/// Alpha-beta search over any variant with a pluggable evaluation.
+///
+/// With more than one thread (`set_threads`) the search is a Lazy SMP one:
+/// helper threads search the same root on their own, sharing only the
+/// transposition table, and fill it with results the main thread then finds.
+/// The main thread's result is the search's result. With one thread (the
+/// default) no thread is started and searches are deterministic.
+pub struct SearchEngine<G: Variant, E: Evaluator<G>> {
+    evaluator: E,
+    tt: TranspositionTable,
+    threads: usize,
+    history: Vec<u32>, // The main thread's history scores, kept between searches
+    _variant: std::marker::PhantomData<fn() -> G>,
+}
+
+impl<G: Variant, E: Evaluator<G>> SearchEngine<G, E> {
+    /// An engine with a transposition table of `tt::DEFAULT_TABLE_MB`.
+    pub fn new(evaluator: E) -> Self {
+        Self::with_table(evaluator, TranspositionTable::new(tt::DEFAULT_TABLE_MB))
+    }
+
+    pub fn with_table(evaluator: E, tt: TranspositionTable) -> Self {
+        SearchEngine {
+            evaluator,
+            tt,
+            threads: 1,
+            history: vec![0; G::SIZE.pow(4)],
+            _variant: std::marker::PhantomData,
+        }
+    }
+
+    pub fn evaluator(&self) -> &E {
+        &self.evaluator
+    }
+
+    pub fn table(&self) -> &TranspositionTable {
+        &self.tt
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Searches with `threads` threads (at least one) from now on.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Forgets everything learned in earlier searches (table, history), e.g.
+    /// before starting a new game.
+    pub fn clear(&mut self) {
+        self.tt.clear();
+        self.history.iter_mut().for_each(|score| *score = 0);
+    }
+
+    /// Searches `position` for the side to move.
+    pub fn search(&mut self, position: &G, limits: &SearchLimits) -> SearchInfo {
+        self.search_with_progress(position, limits, |_| {})
+    }
+
+    /// Like `search`, calling `progress` after every completed iteration.
+    pub fn search_with_progress(
+        &mut self,
+        position: &G,
+        limits: &SearchLimits,
+        progress: impl FnMut(&SearchInfo),
+    ) -> SearchInfo {
+        self.run(position, limits, &[], progress)
+    }
+
+    /// Like `search`, ignoring the root moves in `excluded`: the result is the
+    /// best of the other moves. Searching again with each best move excluded in
+    /// turn ranks the top moves (multi-PV).
+    pub fn search_excluding(&mut self, position: &G, limits: &SearchLimits, excluded: &[Move]) -> SearchInfo {
+        self.run(position, limits, excluded, |_| {})
+    }
+
+    fn run(
+        &mut self,
+        position: &G,
+        limits: &SearchLimits,
+        excluded: &[Move],
+        progress: impl FnMut(&SearchInfo),
+    ) -> SearchInfo {
+        let start = Instant::now();
+        self.history.iter_mut().for_each(|score| *score /= 8);
+        self.tt.new_search();
+
+        let mut root_moves = position.legal_moves();
+        root_moves.retain(|mv| !excluded.contains(mv));
+        if root_moves.is_empty() || position.outcome().is_some() {
+            return SearchInfo::default();
+        }
+        // Start from the move a previous search found best, if there was one
+        if let Some(index) = self.tt.probe(tt::position_hash(position)).and_then(|entry| {
+            entry.best_move.and_then(|mv| root_moves.iter().position(|&root| root == mv))
+        }) {
+            root_moves[..=index].rotate_right(1);
+        }
+
+        let shared = SharedState::default();
+        let history = std::mem::take(&mut self.history);
+        let (evaluator, tt) = (&self.evaluator, &self.tt);
+        let (info, history) = thread::scope(|scope| {
+            for id in 1..self.threads {
+                let mut helper = Worker::new(evaluator, tt, &shared, limits, start, history.clone(), id);
+                let root_moves = root_moves.clone();
+                scope.spawn(move || helper.iterate(position, root_moves, excluded.is_empty(), |_| {}));
+            }
+            let mut main = Worker::new(evaluator, tt, &shared, limits, start, history, 0);
+            let info = main.iterate(position, root_moves, excluded.is_empty(), progress);
+            shared.stop.store(true, Ordering::Relaxed);
+            (info, main.history)
+        });
+        self.history = history;
+        info
+    }
+}
+
+/// State the threads of one search share besides the table.
+#[derive(Default)]
+struct SharedState {
+    stop: AtomicBool, // Set once the main thread is done
+    nodes: AtomicU64, // Nodes of all threads
+}
+
+/// One thread of a search, with its own move ordering state.
+struct Worker<'a, G: Variant, E: Evaluator<G>> {
+    evaluator: &'a E,
+    tt: &'a TranspositionTable,
+    shared: &'a SharedState,
+    id: usize, // 0 for the main thread
+    killers: Vec<[Option<Move>; 2]>,
+    history: Vec<u32>,
+    pv: Vec<Vec<Move>>,
+    nodes: u64,
+    start: Instant,
+    max_depth: u32,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+    stopped: bool,
+    can_stop: bool,
+    _variant: std::marker::PhantomData<fn() -> G>,
+}
+
+impl<'a, G: Variant, E: Evaluator<G>> Worker<'a, G, E> {
+    fn new(
+        evaluator: &'a E,
+        tt: &'a TranspositionTable,
+        shared: &'a SharedState,
+        limits: &SearchLimits,
+        start: Instant,
+        history: Vec<u32>,
+        id: usize,
+    ) -> Self {
+        Worker {
+            evaluator,
+            tt,
+            shared,
+            id,
+            killers: vec![[None; 2]; MAX_PLY],
+            history,
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            nodes: 0,
+            start,
+            max_depth: limits.max_depth,
+            deadline: limits.time.map(|time| start + time),
+            node_limit: limits.nodes,
+            stopped: false,
+            // Only the main thread has to complete depth 1
+            can_stop: id > 0,
+            _variant: std::marker::PhantomData,
+        }
+    }
+
+    /// Iterative deepening over `root_moves`. Every other helper thread starts
+    /// one depth deeper, so the threads spread over neighbouring depths.
+    fn iterate(
+        &mut self,
+        position: &G,
+        mut root_moves: Vec<Move>,
+        store_root: bool,
+        mut progress: impl FnMut(&SearchInfo),
+    ) -> SearchInfo {
+        let side = position.side_to_move();
+        let mut info = SearchInfo::default();
+        let first_depth = 1 + (self.id % 2) as u32;
+
+        for depth in first_depth..=self.max_depth.max(1) {
+            let mut alpha = -INFINITY;
+            let mut best = None;
+            let mut best_pv = Vec::new();
+
+            for (index, &mv) in root_moves.iter().enumerate() {
+                let mut child = position.clone();
+                if child.apply_move(mv).is_err() {
+                    continue;
+                }
+                let score = -self.negamax(&child, depth - 1, -INFINITY, -alpha, opponent(side), 1);
+                if self.stopped {
+                    break;
+                }
+                if score > alpha || best.is_none() {
+                    alpha = score;
+                    best = Some((index, mv));
+                    best_pv = vec![mv];
+                    best_pv.extend_from_slice(&self.pv[1]);
+                }
+            }
+
+            // The previous best move is searched first, so even an interrupted
+            // iteration's best move is at least as well informed
+            if let Some((index, mv)) = best {
+                root_moves[..=index].rotate_right(1);
+                info.best_move = Some(mv);
+                info.score = alpha;
+                info.pv = best_pv;
+            }
+            if self.stopped {
+                break;
+            }
+            // With moves excluded, the score is not the position's own score
+            if store_root {
+                self.tt.store(
+                    tt::position_hash(position),
+                    TtEntry {
+                        best_move: info.best_move,
+                        score: alpha,
+                        depth,
+                        bound: Bound::Exact,
+                    },
+                );
+            }
+            self.can_stop = true;
+            info.depth = depth;
+            info.nodes = self.shared.nodes.load(Ordering::Relaxed);
+            info.elapsed = self.start.elapsed();
+            progress(&info);
+
+            if alpha.abs() >= WIN_THRESHOLD {
+                break;
+            }
+        }
+
+        info.nodes = self.shared.nodes.load(Ordering::Relaxed);
+        info.elapsed = self.start.elapsed();
+        info
+    }
+    fn negamax(&mut self, position: &G, depth: u32, mut alpha: i32, beta: i32, side: CellType, ply: usize) -> i32 {
+        self.nodes += 1;
+        self.shared.nodes.fetch_add(1, Ordering::Relaxed);
+        self.pv[ply].clear();
+        if self.out_of_time() {
+            self.stopped = true;
+            return 0;
+        }
+
+        // The side that made a winning move is still "to move", so results are scored by `side`
+        if let Some(winner) = position.outcome() {
+            return terminal_score(winner, side, ply);
+        }
+        if depth == 0 || ply >= MAX_PLY {
+            return self.evaluator.evaluate(position, side);
+        }
+
+        // Terminal positions are scored above without a lookup, so `side` is always the side to move here
+        let hash = tt::position_hash(position);
+        let entry = self.tt.probe(hash);
+        if let Some(entry) = entry {
+            let score = score_from_table(entry.score, ply);
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => score >= beta,
+                Bound::Upper => score <= alpha,
+            };
+            if entry.depth >= depth && usable {
+                if let Some(mv) = entry.best_move {
+                    self.pv[ply].push(mv);
+                }
+                return score;
+            }
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return -(WIN_SCORE - ply as i32); // A side that cannot move loses
+        }
+        self.order_moves(position, &mut moves, ply);
+        if let Some(index) = entry
+            .and_then(|entry| entry.best_move)
+            .and_then(|mv| moves.iter().position(|&other| other == mv))
+        {
+            moves[..=index].rotate_right(1);
+        }
+
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        for mv in moves {
+            let mut child = position.clone();
+            if child.apply_move(mv).is_err() {
+                continue;
+            }
+            let score = -self.negamax(&child, depth - 1, -beta, -alpha, opponent(side), ply + 1);
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(mv);
+                if score > alpha {
+                    alpha = score;
+                    let child_pv = std::mem::take(&mut self.pv[ply + 1]);
+                    self.pv[ply].clear();
+                    self.pv[ply].push(mv);
+                    self.pv[ply].extend_from_slice(&child_pv);
+                    self.pv[ply + 1] = child_pv;
+                }
+            }
+            if alpha >= beta {
+                if !position.is_capture(mv) {
+                    self.store_killer(mv, ply);
+                    let index = self.history_index(mv);
+                    self.history[index] += depth * depth;
+                }
+                break;
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(
+            hash,
+            TtEntry {
+                best_move,
+                score: score_to_table(best, ply),
+                depth,
+                bound,
+            },
+        );
+        best
+    }
+
+    /// Captures first, then king moves, killer moves and the rest by history score.
+    fn order_moves(&self, position: &G, moves: &mut [Move], ply: usize) {
+        let killers = self.killers[ply];
+        moves.sort_by_cached_key(|&mv| {
+            let mut key: i64 = self.history[self.history_index(mv)] as i64;
+            let captured = position.captured_by(mv).len() as i64;
+            if captured > 0 {
+                key += 4_000_000 + captured * 1_000_000;
+            } else if killers[0] == Some(mv) {
+                key += 2_000_000;
+            } else if killers[1] == Some(mv) {
+                key += 1_500_000;
+            }
+            if position.cell(mv.from).cell_type == CellType::King {
+                key += 3_000_000;
+            }
+            -key
+        });
+    }
+
+    fn store_killer(&mut self, mv: Move, ply: usize) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+    }
+
+    fn history_index(&self, mv: Move) -> usize {
+        let from = mv.from.0 * G::SIZE + mv.from.1;
+        let to = mv.to.0 * G::SIZE + mv.to.1;
+        from * G::SIZE * G::SIZE + to
+    }
+
+    fn out_of_time(&self) -> bool {
+        if !self.can_stop {
+            return false; // Depth 1 always completes, so there is a move to play
+        }
+        if self.shared.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        // The limit is on the nodes of all threads, which with one thread are its own
+        if self.node_limit.is_some_and(|limit| self.shared.nodes.load(Ordering::Relaxed) >= limit) {
+            return true;
+        }
+        // Reading the clock is comparatively slow, so only do it every 1024 nodes
+        self.nodes.is_multiple_of(1024) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+fn terminal_score(winner: CellType, side: CellType, ply: usize) -> i32 {
+    match winner {
+        CellType::Empty => 0,
+        winner if winner == side => WIN_SCORE - ply as i32,
+        _ => -(WIN_SCORE - ply as i32),
+    }
+}
+
+/// Win scores count plies from the root, but a stored position can be
+/// reached at any ply, so the table holds them relative to the position.
+fn score_to_table(score: i32, ply: usize) -> i32 {
+    if score >= WIN_THRESHOLD {
+        score + ply as i32
+    } else if score <= -WIN_THRESHOLD {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: usize) -> i32 {
+    if score >= WIN_THRESHOLD {
+        score - ply as i32
+    } else if score <= -WIN_THRESHOLD {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// Agent that plays the best move found by a `SearchEngine`.
+pub struct SearchAgent<G: Variant, E: Evaluator<G>> {
+    engine: SearchEngine<G, E>,
+    limits: SearchLimits,
+    last_info: SearchInfo,
+}
+
+impl<G: Variant, E: Evaluator<G>> SearchAgent<G, E> {
+    /// The clock passed to `choose_move` can only shorten `limits.time`.
+    pub fn new(evaluator: E, limits: SearchLimits) -> Self {
+        SearchAgent {
+            engine: SearchEngine::new(evaluator),
+            limits,
+            last_info: SearchInfo::default(),
+        }
+    }
+
+    /// Statistics of the last search (depth, nodes, score).
+    pub fn last_info(&self) -> &SearchInfo {
+        &self.last_info
+    }
+}
+
+impl<G: Variant, E: Evaluator<G>> Agent<G> for SearchAgent<G, E> {
+    fn name(&self) -> String {
+        "search".to_string()
+    }
+
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move {
+        let mut limits = self.limits;
+        limits.time = match (limits.time, clock.budget()) {
+            (Some(own), Some(budget)) => Some(own.min(budget)),
+            (own, budget) => own.or(budget),
+        };
+        self.last_info = self.engine.search(position, &limits);
+        self.last_info
+            .best_move
+            .unwrap_or_else(|| position.legal_moves()[0])
+    }
+}
@@ -0,0 +1,159 @@
+//! Forced-win solver for puzzles and endgames: proves whether the side to
+//! move can win (king escape or king capture) within N of its own moves.
+//!
+//! The solver is a depth-limited AND/OR search over the rules engine, deepened
+//! one move at a time so that the shortest win is found. At an OR node the
+//! winning side needs one move that wins; at an AND node every reply of the
+//! other side must still lose. The rules are those of the games themselves:
+//! a side without any legal move loses, as in the simulator and the server,
+//! and reaching `MOVE_LIMIT` is a draw, so no win is proved past it. The
+//! variants have no repetition rule; a line that repeats a position is simply
+//! searched like any other, and can only prove a win by winning within N moves.
+//!
+//! Positions already shown not to be won within some number of moves are
+//! remembered by hash and move count, which keeps transpositions cheap.
+
+use std::collections::HashMap;
+
+use crate::cell::CellType;
+use crate::tt;
+use crate::variant::{Move, Variant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SolveResult {
+    /// A forced win in `moves` moves of the winner. `line` starts with the
+    /// winning move, and the replies in it hold out as long as the solver found.
+    Win { moves: u32, line: Vec<Move> },
+    /// No forced win within the bound.
+    NoWin,
+    /// The node limit ran out before the search finished.
+    Unknown,
+}
+
+pub struct Solver {
+    node_limit: Option<u64>,
+    nodes: u64,
+    stopped: bool,
+    failed: HashMap<(u64, u32), u32>, // (hash, move count) -> most moves known not to win
+}
+
+impl Solver {
+    /// A solver that gives up after `node_limit` positions, if set.
+    pub fn new(node_limit: Option<u64>) -> Self {
+        Solver {
+            node_limit,
+            nodes: 0,
+            stopped: false,
+            failed: HashMap::new(),
+        }
+    }
+
+    /// Positions visited by the last `solve`.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// Whether the side to move of `position` can force a win within `max_moves` of its moves.
+    pub fn solve<G: Variant>(&mut self, position: &G, max_moves: u32) -> SolveResult {
+        self.nodes = 0;
+        self.stopped = false;
+        self.failed.clear();
+        if position.outcome().is_some() {
+            return SolveResult::NoWin;
+        }
+        for moves in 1..=max_moves {
+            if let Some(line) = self.attack(position, moves) {
+                return SolveResult::Win { moves, line };
+            }
+            if self.stopped {
+                return SolveResult::Unknown;
+            }
+        }
+        SolveResult::NoWin
+    }
+
+    /// A winning line for the side to move within `moves` moves, if there is one.
+    fn attack<G: Variant>(&mut self, position: &G, moves: u32) -> Option<Vec<Move>> {
+        let key = (tt::position_hash(position), position.move_count());
+        if self.failed.get(&key).is_some_and(|&failed| failed >= moves) || !self.visit() {
+            return None;
+        }
+        let side = position.side_to_move();
+        let mut children = Vec::new();
+        for mv in position.legal_moves() {
+            let mut child = position.clone();
+            if child.apply_move(mv).is_err() {
+                continue;
+            }
+            match child.outcome() {
+                Some(winner) if winner == side => return Some(vec![mv]),
+                Some(_) => {}
+                // A side without any move loses
+                None if !child.has_legal_move() => return Some(vec![mv]),
+                None => children.push((mv, child)),
+            }
+        }
+
+        if moves > 1 {
+            // Moves that capture or move the king are the likeliest to force something
+            children.sort_by_key(|(mv, _)| {
+                let king = position.cell(mv.from).cell_type == CellType::King;
+                !(king || position.is_capture(*mv))
+            });
+            for (mv, child) in children {
+                if let Some(defence) = self.defend(&child, side, moves - 1) {
+                    let mut line = vec![mv];
+                    line.extend(defence);
+                    return Some(line);
+                }
+                if self.stopped {
+                    return None;
+                }
+            }
+        }
+        if !self.stopped {
+            self.failed.insert(key, moves);
+        }
+        None
+    }
+
+    /// With `attacker` to win within `moves` (at least one) more moves, the
+    /// longest-lasting reply of the side to move and the rest of the line, if
+    /// every reply loses.
+    fn defend<G: Variant>(&mut self, position: &G, attacker: CellType, moves: u32) -> Option<Vec<Move>> {
+        if !self.visit() {
+            return None;
+        }
+        let mut longest: Option<Vec<Move>> = None;
+        for mv in position.legal_moves() {
+            let mut child = position.clone();
+            if child.apply_move(mv).is_err() {
+                continue;
+            }
+            let rest = match child.outcome() {
+                Some(winner) if winner == attacker => Vec::new(),
+                Some(_) => return None,
+                None => self.attack(&child, moves)?,
+            };
+            if longest.as_ref().is_none_or(|line| rest.len() + 1 > line.len()) {
+                let mut line = vec![mv];
+                line.extend(rest);
+                longest = Some(line);
+            }
+        }
+        Some(longest.unwrap_or_default())
+    }
+
+    fn visit(&mut self) -> bool {
+        self.nodes += 1;
+        if self.node_limit.is_some_and(|limit| self.nodes > limit) {
+            self.stopped = true;
+        }
+        !self.stopped
+    }
+}
+
+/// Solves `position` without a node limit.
+pub fn solve<G: Variant>(position: &G, max_moves: u32) -> SolveResult {
+    Solver::new(None).solve(position, max_moves)
+}
@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::cell::CellType;
+
+/// Outcome of one finished game, as recorded per game in the results.
+#[derive(Debug, Clone, Copy)]
+pub struct GameRecord {
+    pub winner: CellType, // `CellType::Empty` for a draw
+    pub attacker_moves: u32,
+    pub defender_moves: u32,
+    pub seed: Option<u64>, // Seed of the game's random components, if any
+}
+
+/// Aggregate statistics over a session (or a simulation run) of several games.
+#[derive(Debug, Clone, Default)]
+pub struct GameStats {
+    pub game_id: u32,
+    pub total_games: u32,
+    pub total_attacker_moves: u32,
+    pub total_defender_moves: u32,
+    pub attacker_wins: u32,
+    pub defender_wins: u32,
+    pub ties: u32,
+    pub attacker_move_time: Duration,
+    pub defender_move_time: Duration,
+    pub attacker_timed_moves: u32,
+    pub defender_timed_moves: u32,
+    pub attacker_moves: Vec<u32>, // Moves of each game won by the attacker
+    pub defender_moves: Vec<u32>, // Moves of each game won by the defender
+    pub attacker_player: Option<String>, // Who played the attackers, if known
+    pub defender_player: Option<String>, // Who played the defenders, if known
+    pub seed: Option<u64>, // Seed of the session's random components, if any
+}
+
+impl GameStats {
+    pub fn new(game_id: u32) -> Self {
+        GameStats {
+            game_id,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the time one side spent choosing a move.
+    pub fn record_move_duration(&mut self, role: CellType, duration: Duration) {
+        if role == CellType::Attacker {
+            self.attacker_move_time += duration;
+            self.attacker_timed_moves += 1;
+        } else {
+            self.defender_move_time += duration;
+            self.defender_timed_moves += 1;
+        }
+    }
+
+    /// Counts a finished game towards the totals.
+    pub fn record_game(&mut self, record: &GameRecord) {
+        self.total_games += 1;
+        match record.winner {
+            CellType::Attacker => {
+                self.attacker_wins += 1;
+                self.total_attacker_moves += record.attacker_moves;
+                self.attacker_moves.push(record.attacker_moves);
+            }
+            CellType::Defender => {
+                self.defender_wins += 1;
+                self.total_defender_moves += record.defender_moves;
+                self.defender_moves.push(record.defender_moves);
+            }
+            CellType::Empty => self.ties += 1,
+            _ => {}
+        }
+    }
+
+    /// Folds the statistics gathered by another worker into these.
+    pub fn merge(&mut self, other: GameStats) {
+        self.total_games += other.total_games;
+        self.total_attacker_moves += other.total_attacker_moves;
+        self.total_defender_moves += other.total_defender_moves;
+        self.attacker_wins += other.attacker_wins;
+        self.defender_wins += other.defender_wins;
+        self.ties += other.ties;
+        self.attacker_move_time += other.attacker_move_time;
+        self.defender_move_time += other.defender_move_time;
+        self.attacker_timed_moves += other.attacker_timed_moves;
+        self.defender_timed_moves += other.defender_timed_moves;
+        self.attacker_moves.extend(other.attacker_moves);
+        self.defender_moves.extend(other.defender_moves);
+    }
+
+    pub fn average_move_duration_attacker(&self) -> Duration {
+        self.attacker_move_time
+            .checked_div(self.attacker_timed_moves)
+            .unwrap_or_default()
+    }
+
+    pub fn average_move_duration_defender(&self) -> Duration {
+        self.defender_move_time
+            .checked_div(self.defender_timed_moves)
+            .unwrap_or_default()
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "Average attacker moves per winning game: {:.2}",
+            self.total_attacker_moves as f64 / self.attacker_wins as f64
+        );
+        println!(
+            "Average defender moves per winning game: {:.2}",
+            self.total_defender_moves as f64 / self.defender_wins as f64
+        );
+        println!("Average move duration for attacker: {:?}", self.average_move_duration_attacker());
+        println!("Average move duration for defender: {:?}", self.average_move_duration_defender());
+        println!("Attacker wins: {}", self.attacker_wins);
+        println!("Defender wins: {}", self.defender_wins);
+        println!("Ties: {}", self.ties);
+    }
+
+    /// Writes the results file read by the plotting scripts.
+    pub fn write_results(&self, file_name: &str) -> io::Result<()> {
+        let mut results_file = File::create(file_name)?;
+        writeln!(results_file, "Session ID: {}", self.game_id)?;
+        if let Some(player) = &self.attacker_player {
+            writeln!(results_file, "Attacker: {}", player)?;
+        }
+        if let Some(player) = &self.defender_player {
+            writeln!(results_file, "Defender: {}", player)?;
+        }
+        if let Some(seed) = self.seed {
+            writeln!(results_file, "Seed: {}", seed)?;
+        }
+        writeln!(results_file, "Total games: {}", self.total_games)?;
+        writeln!(results_file, "Average attacker moves per winning game: {:.2}", self.total_attacker_moves as f64 / self.total_games as f64)?;
+        writeln!(results_file, "Average defender moves per winning game: {:.2}", self.total_defender_moves as f64 / self.total_games as f64)?;
+        writeln!(results_file, "Average move duration for attacker: {:?}", self.average_move_duration_attacker())?;
+        writeln!(results_file, "Average move duration for defender: {:?}", self.average_move_duration_defender())?;
+        writeln!(results_file, "Attacker wins: {}", self.attacker_wins)?;
+        writeln!(results_file, "Defender wins: {}", self.defender_wins)?;
+        writeln!(results_file, "Ties: {}", self.ties)?;
+        Ok(())
+    }
+}
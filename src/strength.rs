@@ -0,0 +1,179 @@
+//! Named difficulty levels for the search engine, for playing against people.
+//!
+//! A level limits the search by nodes (so that it costs about the same on
+//! every board size) and depth, and weakens the move choice on top of that:
+//! the engine ranks a few candidate moves, adds random noise to their scores
+//! before picking the best, and now and then overlooks every capture.
+
+use std::fmt;
+use std::time::Instant;
+
+use rand::Rng;
+
+use crate::agent::{Agent, Clock};
+use crate::eval::{WeightedEvaluator, Weights};
+use crate::rng::{self, GameRng};
+use crate::search::{SearchEngine, SearchLimits};
+use crate::variant::{Move, Variant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Level {
+    Beginner,
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+/// How a level searches and chooses its moves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelSettings {
+    pub max_depth: u32,
+    pub nodes: u64,           // Node limit of each candidate's search
+    pub candidates: usize,    // Number of best moves the choice is made from
+    pub noise: i32,           // Random bonus of up to this much added to each candidate's score
+    pub missed_captures: f64, // Chance of not seeing any capture on a move
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [Level::Beginner, Level::Easy, Level::Medium, Level::Hard, Level::Expert];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Beginner => "beginner",
+            Level::Easy => "easy",
+            Level::Medium => "medium",
+            Level::Hard => "hard",
+            Level::Expert => "expert",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|level| level.name() == name.to_lowercase())
+    }
+
+    /// Level `number`, from 1 (beginner) to 5 (expert).
+    pub fn from_number(number: u32) -> Option<Level> {
+        Level::ALL.get((number as usize).checked_sub(1)?).copied()
+    }
+
+    pub fn number(self) -> u32 {
+        Level::ALL.iter().position(|&level| level == self).unwrap() as u32 + 1
+    }
+
+    pub fn settings(self) -> LevelSettings {
+        match self {
+            Level::Beginner => LevelSettings {
+                max_depth: 1,
+                nodes: 500,
+                candidates: 4,
+                noise: 300,
+                missed_captures: 0.5,
+            },
+            Level::Easy => LevelSettings {
+                max_depth: 2,
+                nodes: 2_000,
+                candidates: 3,
+                noise: 150,
+                missed_captures: 0.25,
+            },
+            Level::Medium => LevelSettings {
+                max_depth: 3,
+                nodes: 10_000,
+                candidates: 3,
+                noise: 60,
+                missed_captures: 0.1,
+            },
+            Level::Hard => LevelSettings {
+                max_depth: 5,
+                nodes: 50_000,
+                candidates: 2,
+                noise: 20,
+                missed_captures: 0.0,
+            },
+            Level::Expert => LevelSettings {
+                max_depth: 64,
+                nodes: 300_000,
+                candidates: 1,
+                noise: 0,
+                missed_captures: 0.0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Plays at a given `Level`.
+pub struct StrengthAgent<G: Variant> {
+    level: Level,
+    engine: SearchEngine<G, WeightedEvaluator>,
+    rng: GameRng,
+}
+
+impl<G: Variant> StrengthAgent<G> {
+    /// `seed` drives the level's randomness.
+    pub fn new(level: Level, seed: u64) -> Self {
+        StrengthAgent {
+            level,
+            engine: SearchEngine::new(WeightedEvaluator::new(Weights::default())),
+            rng: rng::rng_from_seed(seed),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+}
+
+impl<G: Variant> Agent<G> for StrengthAgent<G> {
+    fn name(&self) -> String {
+        format!("level:{}", self.level)
+    }
+
+    fn choose_move(&mut self, position: &G, clock: &Clock) -> Move {
+        let settings = self.level.settings();
+        let moves = position.legal_moves();
+
+        // Overlooked captures are left out of the search, unless nothing else is left
+        let mut excluded = Vec::new();
+        if self.rng.random_bool(settings.missed_captures) {
+            excluded = moves.iter().copied().filter(|&mv| position.is_capture(mv)).collect();
+            if excluded.len() == moves.len() {
+                excluded.clear();
+            }
+        }
+
+        // The candidate searches share the move's time budget: each gets an
+        // equal share of what the earlier ones left
+        let deadline = clock.budget().map(|budget| Instant::now() + budget);
+        // Wins and losses are far apart from any noise, so a found win is never passed up
+        let mut best: Option<(i32, Move)> = None;
+        for index in 0..settings.candidates {
+            let time = deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()) / (settings.candidates - index) as u32);
+            if best.is_some() && time.is_some_and(|time| time.is_zero()) {
+                break;
+            }
+            let limits = SearchLimits {
+                max_depth: settings.max_depth,
+                time,
+                nodes: Some(settings.nodes),
+            };
+            let info = self.engine.search_excluding(position, &limits, &excluded);
+            let Some(mv) = info.best_move else {
+                break;
+            };
+            excluded.push(mv);
+            let score = info.score + self.rng.random_range(0..=settings.noise);
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, mv));
+            }
+        }
+        best.map_or(moves[0], |(_, mv)| mv)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::variant::{Move, Variant};
+
+/// One of the 8 symmetries of the square board (rotations and reflections).
+///
+/// Corners and the throne map onto themselves under all of them, so a
+/// transformed position follows the same rules as the original.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    Rotate90, // Clockwise
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,   // Mirror left-right
+    FlipVertical,     // Mirror top-bottom
+    FlipDiagonal,     // Transpose (main diagonal)
+    FlipAntiDiagonal, // Transpose (anti-diagonal)
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::FlipDiagonal,
+        Symmetry::FlipAntiDiagonal,
+    ];
+
+    /// Maps a square of a `size` x `size` board.
+    pub fn apply(self, pos: (usize, usize), size: usize) -> (usize, usize) {
+        let (row, col) = pos;
+        let last = size - 1;
+        match self {
+            Symmetry::Identity => (row, col),
+            Symmetry::Rotate90 => (col, last - row),
+            Symmetry::Rotate180 => (last - row, last - col),
+            Symmetry::Rotate270 => (last - col, row),
+            Symmetry::FlipHorizontal => (row, last - col),
+            Symmetry::FlipVertical => (last - row, col),
+            Symmetry::FlipDiagonal => (col, row),
+            Symmetry::FlipAntiDiagonal => (last - col, last - row),
+        }
+    }
+
+    pub fn apply_move(self, mv: Move, size: usize) -> Move {
+        Move {
+            from: self.apply(mv.from, size),
+            to: self.apply(mv.to, size),
+        }
+    }
+
+    /// The symmetry that undoes this one.
+    pub fn inverse(self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => other, // Every other symmetry is its own inverse
+        }
+    }
+}
+
+/// `position_key` of the transformed position, without building it.
+pub(crate) fn transformed_key<G: Variant>(game: &G, symmetry: Symmetry) -> Vec<u8> {
+    let inverse = symmetry.inverse();
+    let mut key = Vec::with_capacity(G::SIZE * G::SIZE + 1);
+    for row in 0..G::SIZE {
+        for col in 0..G::SIZE {
+            key.push(game.cell(inverse.apply((row, col), G::SIZE)).cell_type as u8);
+        }
+    }
+    key.push(game.side_to_move() as u8);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brandubh;
+    use crate::copenhagen;
+    use crate::test_util::random_moves;
+
+    fn check_inverse_round_trip<G: Variant>() {
+        for symmetry in Symmetry::ALL {
+            for row in 0..G::SIZE {
+                for col in 0..G::SIZE {
+                    let square = symmetry.apply((row, col), G::SIZE);
+                    assert_eq!(symmetry.inverse().apply(square, G::SIZE), (row, col), "{:?}", symmetry);
+                }
+            }
+            for mv in G::new_game().legal_moves() {
+                let image = symmetry.apply_move(mv, G::SIZE);
+                assert_eq!(symmetry.inverse().apply_move(image, G::SIZE), mv, "{:?}", symmetry);
+            }
+        }
+    }
+
+    /// A position a few random moves into a game, without any symmetry of its own.
+    fn asymmetric_position<G: Variant>() -> G {
+        let mut game = G::new_game();
+        for mv in random_moves::<G>(6, 11) {
+            game.play(mv).unwrap();
+        }
+        assert_eq!(game.self_symmetries(), vec![Symmetry::Identity]);
+        game
+    }
+
+    fn check_canonical_form_is_shared<G: Variant>() {
+        let game = asymmetric_position::<G>();
+        let (canonical, _) = game.canonical_form();
+        let keys: Vec<Vec<u8>> = Symmetry::ALL
+            .iter()
+            .map(|&symmetry| game.transformed(symmetry).position_key())
+            .collect();
+        for (index, &symmetry) in Symmetry::ALL.iter().enumerate() {
+            let image = game.transformed(symmetry);
+            // Every image is a different position, which maps back with the inverse
+            assert_eq!(keys.iter().filter(|&key| *key == keys[index]).count(), 1, "{:?}", symmetry);
+            assert_eq!(image.transformed(symmetry.inverse()).position_key(), game.position_key());
+
+            let (image_canonical, image_symmetry) = image.canonical_form();
+            assert_eq!(image_canonical.position_key(), canonical.position_key(), "{:?}", symmetry);
+            assert_eq!(image.transformed(image_symmetry).position_key(), canonical.position_key());
+        }
+    }
+
+    #[test]
+    fn inverse_round_trips() {
+        check_inverse_round_trip::<brandubh::GameState>();
+        check_inverse_round_trip::<copenhagen::GameState>();
+    }
+
+    #[test]
+    fn images_share_the_canonical_form() {
+        check_canonical_form_is_shared::<brandubh::GameState>();
+        check_canonical_form_is_shared::<copenhagen::GameState>();
+    }
+}
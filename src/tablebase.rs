@@ -0,0 +1,816 @@
+//! Brandubh endgame tablebase built by retrograde analysis.
+//!
+//! A table holds every placement of the king plus a given number of attackers
+//! and defenders, for both sides to move, with its result under perfect play:
+//! win or loss for the side to move and the number of plies to the end of the
+//! game, or a draw when neither side can force a win. Tables are generated
+//! from the fewest pieces up, since captures lead into smaller tables, and
+//! every move is played with the rules engine itself, so the tables follow
+//! exactly the same rules as the games.
+//!
+//! The move limit is not part of the tables: a win in more plies than the game
+//! has left before `MOVE_LIMIT` is a draw in practice.
+//!
+//! On disk a tablebase is `b"BTB"`, a format version byte and the piece count,
+//! followed by each table: attacker count, defender count, the number of
+//! positions as a little-endian `u32` and one little-endian `u16` per
+//! position (see `WIN`, `LOSS`, `DRAW` and `INVALID`).
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::agent::{Agent, Clock};
+use crate::brandubh::GameState;
+use crate::cell::{Cell, CellType};
+use crate::variant::{opponent, Move, Variant};
+
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Most pieces (king included) a tablebase can be generated for. Five pieces
+/// would take about 300 million positions.
+pub const MAX_PIECES: usize = 4;
+
+const MAGIC: &[u8; 3] = b"BTB";
+const SIZE: usize = 7;
+const SQUARES: usize = SIZE * SIZE;
+const THRONE: usize = 3 * SIZE + 3;
+const CORNERS: [usize; 4] = [0, SIZE - 1, SQUARES - SIZE, SQUARES - 1];
+const MAX_ATTACKERS: usize = 8;
+const MAX_DEFENDERS: usize = 4;
+
+// Stored values: the top two bits give the result for the side to move, the
+// rest the distance in plies
+const DRAW: u16 = 0;
+const WIN: u16 = 0x4000;
+const LOSS: u16 = 0x8000;
+const INVALID: u16 = 0xffff; // Not a legal placement (piece on a corner, ...)
+const UNKNOWN: u16 = 0xfffe; // Not solved yet, only used while generating
+const DISTANCE_MASK: u16 = 0x3fff;
+
+/// Result of a position for the side to move, with the number of plies until
+/// the game ends under perfect play (the winner wins as fast as possible, the
+/// loser holds out as long as possible).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TablebaseResult {
+    Win(u32),
+    Loss(u32),
+    Draw,
+}
+
+impl TablebaseResult {
+    fn decode(value: u16) -> Option<TablebaseResult> {
+        match value {
+            INVALID | UNKNOWN => None,
+            DRAW => Some(TablebaseResult::Draw),
+            _ if value & WIN != 0 => Some(TablebaseResult::Win((value & DISTANCE_MASK) as u32)),
+            _ => Some(TablebaseResult::Loss((value & DISTANCE_MASK) as u32)),
+        }
+    }
+
+    /// Result for the side that played the move into a position with this result.
+    fn for_mover(self) -> TablebaseResult {
+        match self {
+            TablebaseResult::Win(distance) => TablebaseResult::Loss(distance + 1),
+            TablebaseResult::Loss(distance) => TablebaseResult::Win(distance + 1),
+            TablebaseResult::Draw => TablebaseResult::Draw,
+        }
+    }
+
+    /// Orders results from the point of view of the side choosing between them.
+    fn preference(self) -> i64 {
+        match self {
+            TablebaseResult::Win(distance) => i64::MAX - distance as i64,
+            TablebaseResult::Draw => 0,
+            TablebaseResult::Loss(distance) => i64::MIN + distance as i64,
+        }
+    }
+}
+
+/// Statistics of one generated table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableInfo {
+    pub attackers: usize,
+    pub defenders: usize,
+    pub positions: usize, // Legal placements, both sides to move
+    pub wins: usize,      // Won for the side to move
+    pub losses: usize,
+    pub draws: usize,
+    pub longest: u32, // Longest forced win, in plies
+    pub elapsed: Duration,
+}
+
+pub struct Tablebase {
+    max_pieces: usize,
+    tables: BTreeMap<(usize, usize), Vec<u16>>, // By (attackers, defenders)
+}
+
+impl Tablebase {
+    /// Solves every position with at most `max_pieces` pieces, king included.
+    pub fn generate(max_pieces: usize) -> Result<Tablebase, String> {
+        Self::generate_with_progress(max_pieces, |_| {})
+    }
+
+    /// Like `generate`, calling `progress` after every table.
+    pub fn generate_with_progress(max_pieces: usize, mut progress: impl FnMut(&TableInfo)) -> Result<Tablebase, String> {
+        if !(1..=MAX_PIECES).contains(&max_pieces) {
+            return Err(format!("Piece count must be between 1 and {}", MAX_PIECES));
+        }
+        let mut tablebase = Tablebase {
+            max_pieces,
+            tables: BTreeMap::new(),
+        };
+        for others in 0..max_pieces {
+            for attackers in 0..=others.min(MAX_ATTACKERS) {
+                let defenders = others - attackers;
+                if defenders > MAX_DEFENDERS {
+                    continue;
+                }
+                let start = Instant::now();
+                let table = Generator::new(&tablebase, attackers, defenders).solve();
+                let mut info = table_info(attackers, defenders, &table);
+                info.elapsed = start.elapsed();
+                tablebase.tables.insert((attackers, defenders), table);
+                progress(&info);
+            }
+        }
+        Ok(tablebase)
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Statistics of every table, smallest first.
+    pub fn tables(&self) -> Vec<TableInfo> {
+        self.tables
+            .iter()
+            .map(|(&(attackers, defenders), table)| table_info(attackers, defenders, table))
+            .collect()
+    }
+
+    /// Result of `position` for the side to move, or `None` if it is not in the
+    /// tablebase (too many pieces, no king, or the game is already over).
+    pub fn probe(&self, position: &GameState) -> Option<TablebaseResult> {
+        if position.outcome().is_some() {
+            return None;
+        }
+        let placement = Placement::of(position)?;
+        let table = self.tables.get(&placement.material())?;
+        TablebaseResult::decode(table[placement.index()])
+    }
+
+    /// The best move in `position` and the result it leads to for the side to
+    /// move, or `None` if the position is not in the tablebase.
+    pub fn best_move(&self, position: &GameState) -> Option<(Move, TablebaseResult)> {
+        self.probe(position)?;
+        let side = position.side_to_move();
+        position
+            .legal_moves()
+            .into_iter()
+            .filter_map(|mv| {
+                let mut child = position.clone();
+                child.apply_move(mv).ok()?;
+                let result = match child.winner() {
+                    Some(winner) if winner == side => TablebaseResult::Win(1),
+                    Some(_) => TablebaseResult::Loss(1),
+                    None => self.probe(&child)?.for_mover(),
+                };
+                Some((mv, result))
+            })
+            .max_by_key(|&(_, result)| result.preference())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[FORMAT_VERSION, self.max_pieces as u8])?;
+        for (&(attackers, defenders), table) in &self.tables {
+            file.write_all(&[attackers as u8, defenders as u8])?;
+            file.write_all(&(table.len() as u32).to_le_bytes())?;
+            for value in table {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+        file.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Tablebase, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+        Self::read(&mut BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Tablebase, String> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header).map_err(|_| "Not a tablebase file".to_string())?;
+        if &header[..3] != MAGIC {
+            return Err("Not a tablebase file".to_string());
+        }
+        if header[3] != FORMAT_VERSION {
+            return Err(format!("Unsupported format version {} (expected {})", header[3], FORMAT_VERSION));
+        }
+        let max_pieces = header[4] as usize;
+
+        let mut tables = BTreeMap::new();
+        loop {
+            let mut material = [0; 2];
+            match reader.read_exact(&mut material) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.to_string()),
+            }
+            let (attackers, defenders) = (material[0] as usize, material[1] as usize);
+            if attackers > MAX_ATTACKERS || defenders > MAX_DEFENDERS || attackers + defenders >= max_pieces {
+                return Err(format!("Unexpected table for {} attackers and {} defenders", attackers, defenders));
+            }
+            let mut len = [0; 4];
+            reader.read_exact(&mut len).map_err(|e| e.to_string())?;
+            let len = u32::from_le_bytes(len) as usize;
+            if len != table_len(attackers, defenders) {
+                return Err(format!("Table for {} attackers and {} defenders has the wrong size", attackers, defenders));
+            }
+            let mut bytes = vec![0; len * 2];
+            reader.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+            let table = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+            tables.insert((attackers, defenders), table);
+        }
+        Ok(Tablebase { max_pieces, tables })
+    }
+}
+
+fn table_info(attackers: usize, defenders: usize, table: &[u16]) -> TableInfo {
+    let mut info = TableInfo {
+        attackers,
+        defenders,
+        ..Default::default()
+    };
+    for &value in table {
+        match TablebaseResult::decode(value) {
+            Some(TablebaseResult::Win(distance)) => {
+                info.wins += 1;
+                info.longest = info.longest.max(distance);
+            }
+            Some(TablebaseResult::Loss(_)) => info.losses += 1,
+            Some(TablebaseResult::Draw) => info.draws += 1,
+            None => continue,
+        }
+        info.positions += 1;
+    }
+    info
+}
+
+/// Plays the tablebase move whenever the position is in the tablebase, and
+/// leaves every other position to `fallback`.
+pub struct TablebaseAgent<A: Agent<GameState>> {
+    tablebase: Arc<Tablebase>,
+    fallback: A,
+    in_tablebase: bool, // Whether the last move came from the tablebase
+}
+
+impl<A: Agent<GameState>> TablebaseAgent<A> {
+    pub fn new(tablebase: Arc<Tablebase>, fallback: A) -> Self {
+        TablebaseAgent {
+            tablebase,
+            fallback,
+            in_tablebase: false,
+        }
+    }
+}
+
+impl<A: Agent<GameState>> Agent<GameState> for TablebaseAgent<A> {
+    fn name(&self) -> String {
+        format!("{}+tablebase", self.fallback.name())
+    }
+
+    fn choose_move(&mut self, position: &GameState, clock: &Clock) -> Move {
+        let tablebase_move = self.tablebase.best_move(position);
+        self.in_tablebase = tablebase_move.is_some();
+        match tablebase_move {
+            Some((mv, _)) => mv,
+            None => self.fallback.choose_move(position, clock),
+        }
+    }
+
+    fn try_choose_move(&mut self, position: &GameState, clock: &Clock) -> Result<Move, String> {
+        let tablebase_move = self.tablebase.best_move(position);
+        self.in_tablebase = tablebase_move.is_some();
+        match tablebase_move {
+            Some((mv, _)) => Ok(mv),
+            None => self.fallback.try_choose_move(position, clock),
+        }
+    }
+
+    fn move_weights(&self) -> Option<Vec<(Move, u32)>> {
+        if self.in_tablebase { None } else { self.fallback.move_weights() }
+    }
+}
+
+/// Binomial coefficients `C(n, k)` for every `n` up to the number of squares.
+const BINOMIAL: [[usize; MAX_ATTACKERS + 1]; SQUARES + 1] = binomial_table();
+
+const fn binomial_table() -> [[usize; MAX_ATTACKERS + 1]; SQUARES + 1] {
+    let mut table = [[0; MAX_ATTACKERS + 1]; SQUARES + 1];
+    let mut n = 0;
+    while n <= SQUARES {
+        table[n][0] = 1;
+        let mut k = 1;
+        while k <= MAX_ATTACKERS && n > 0 {
+            table[n][k] = table[n - 1][k - 1] + table[n - 1][k];
+            k += 1;
+        }
+        n += 1;
+    }
+    table
+}
+
+fn table_len(attackers: usize, defenders: usize) -> usize {
+    2 * SQUARES * BINOMIAL[SQUARES - 1][attackers] * BINOMIAL[SQUARES - 1 - attackers][defenders]
+}
+
+/// Rank of a sorted set of indices among all sets of the same size (colex order).
+fn rank(indices: &[usize]) -> usize {
+    indices.iter().enumerate().map(|(i, &index)| BINOMIAL[index][i + 1]).sum()
+}
+
+/// The sorted set of `count` indices with the given rank.
+fn unrank(mut rank: usize, count: usize, indices: &mut Vec<usize>) {
+    indices.clear();
+    let mut candidate = SQUARES;
+    for k in (1..=count).rev() {
+        candidate -= 1;
+        while BINOMIAL[candidate][k] > rank {
+            candidate -= 1;
+        }
+        rank -= BINOMIAL[candidate][k];
+        indices.push(candidate);
+    }
+    indices.reverse();
+}
+
+/// Where the pieces stand, as square indices (`row * 7 + col`), sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Placement {
+    side: CellType,
+    king: usize,
+    attackers: Vec<usize>,
+    defenders: Vec<usize>,
+}
+
+impl Placement {
+    fn of(position: &GameState) -> Option<Placement> {
+        let mut king = None;
+        let mut attackers = Vec::new();
+        let mut defenders = Vec::new();
+        for square in 0..SQUARES {
+            match position.cell((square / SIZE, square % SIZE)).cell_type {
+                CellType::King => king = Some(square),
+                CellType::Attacker => attackers.push(square),
+                CellType::Defender => defenders.push(square),
+                CellType::Empty => {}
+            }
+        }
+        let placement = Placement {
+            side: position.side_to_move(),
+            king: king?,
+            attackers,
+            defenders,
+        };
+        (placement.attackers.len() <= MAX_ATTACKERS && placement.defenders.len() <= MAX_DEFENDERS).then_some(placement)
+    }
+
+    fn material(&self) -> (usize, usize) {
+        (self.attackers.len(), self.defenders.len())
+    }
+
+    fn is_valid(&self) -> bool {
+        !CORNERS.contains(&self.king)
+            && self
+                .attackers
+                .iter()
+                .chain(&self.defenders)
+                .all(|square| *square != THRONE && !CORNERS.contains(square))
+    }
+
+    /// Position of the placement in its table. The attackers are ranked among
+    /// the squares other than the king's, the defenders among those left.
+    fn index(&self) -> usize {
+        let (attackers, defenders) = self.material();
+        let reduced_attackers: Vec<usize> = self
+            .attackers
+            .iter()
+            .map(|&square| square - usize::from(square > self.king))
+            .collect();
+        let reduced_defenders: Vec<usize> = self
+            .defenders
+            .iter()
+            .map(|&square| {
+                let taken = self.attackers.iter().filter(|&&attacker| attacker < square).count();
+                square - taken - usize::from(square > self.king)
+            })
+            .collect();
+
+        let mut index = usize::from(self.side == CellType::Defender) * SQUARES + self.king;
+        index = index * BINOMIAL[SQUARES - 1][attackers] + rank(&reduced_attackers);
+        index * BINOMIAL[SQUARES - 1 - attackers][defenders] + rank(&reduced_defenders)
+    }
+
+    fn from_index(attackers: usize, defenders: usize, index: usize) -> Placement {
+        let defender_sets = BINOMIAL[SQUARES - 1 - attackers][defenders];
+        let attacker_sets = BINOMIAL[SQUARES - 1][attackers];
+        let defender_rank = index % defender_sets;
+        let index = index / defender_sets;
+        let attacker_rank = index % attacker_sets;
+        let index = index / attacker_sets;
+        let king = index % SQUARES;
+        let side = if index / SQUARES == 0 { CellType::Attacker } else { CellType::Defender };
+
+        let mut placement = Placement {
+            side,
+            king,
+            attackers: Vec::with_capacity(attackers),
+            defenders: Vec::with_capacity(defenders),
+        };
+        unrank(attacker_rank, attackers, &mut placement.attackers);
+        for square in placement.attackers.iter_mut() {
+            *square += usize::from(*square >= king);
+        }
+        let free: Vec<usize> = (0..SQUARES)
+            .filter(|square| *square != king && !placement.attackers.contains(square))
+            .collect();
+        unrank(defender_rank, defenders, &mut placement.defenders);
+        for square in placement.defenders.iter_mut() {
+            *square = free[*square];
+        }
+        placement
+    }
+
+    fn to_game(&self, empty: &GameState) -> GameState {
+        let mut game = empty.clone();
+        let mut place = |square: usize, cell_type: CellType| {
+            let pos = (square / SIZE, square % SIZE);
+            game.set_cell(pos, Cell { cell_type, ..empty.cell(pos) });
+        };
+        place(self.king, CellType::King);
+        self.attackers.iter().for_each(|&square| place(square, CellType::Attacker));
+        self.defenders.iter().for_each(|&square| place(square, CellType::Defender));
+        game.set_side_to_move(self.side);
+        game
+    }
+
+    /// Whether `position`, reached by one move from this placement, has fewer pieces.
+    fn is_capture(&self, position: &GameState) -> bool {
+        position.count_pieces(CellType::Attacker) != self.attackers.len()
+            || position.count_pieces(CellType::Defender) != self.defenders.len()
+    }
+
+    fn is_empty(&self, square: usize) -> bool {
+        square != self.king && !self.attackers.contains(&square) && !self.defenders.contains(&square)
+    }
+}
+
+/// What the forward pass learns about a position from its moves.
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    remaining: u8,     // Moves within the table not yet known to lose for the mover
+    has_draw: bool,    // A move into a smaller table draws
+    best_win: u16,     // Shortest win by a move that ends the game or leaves the table (0 if none)
+    longest_loss: u16, // Longest loss among the moves resolved so far
+}
+
+/// Solves the table for one material balance, given all smaller tables.
+struct Generator<'a> {
+    smaller: &'a Tablebase,
+    attackers: usize,
+    defenders: usize,
+    empty: GameState,
+    values: Vec<u16>,
+    nodes: Vec<Node>,
+    buckets: Vec<Vec<(u32, u16)>>, // Positions to settle, by distance
+}
+
+impl<'a> Generator<'a> {
+    fn new(smaller: &'a Tablebase, attackers: usize, defenders: usize) -> Self {
+        Generator {
+            smaller,
+            attackers,
+            defenders,
+            empty: GameState::empty_board(),
+            values: Vec::new(),
+            nodes: Vec::new(),
+            buckets: Vec::new(),
+        }
+    }
+
+    fn solve(mut self) -> Vec<u16> {
+        self.forward_pass();
+
+        for index in 0..self.values.len() {
+            if self.values[index] == INVALID {
+                continue;
+            }
+            let node = self.nodes[index];
+            if node.best_win > 0 {
+                self.push(index, WIN | node.best_win);
+            } else if node.remaining == 0 {
+                self.settle_without_win(index);
+            }
+        }
+
+        // Settle positions in order of distance, so every win is the fastest
+        // and every loss the slowest available
+        let mut distance = 0;
+        while distance < self.buckets.len() {
+            while let Some((index, value)) = self.buckets[distance].pop() {
+                let index = index as usize;
+                if self.values[index] != UNKNOWN {
+                    continue;
+                }
+                self.values[index] = value;
+                self.update_predecessors(index, value);
+            }
+            distance += 1;
+        }
+
+        // Whatever could not be forced either way is a draw
+        for value in self.values.iter_mut() {
+            if *value == UNKNOWN {
+                *value = DRAW;
+            }
+        }
+        self.values
+    }
+
+    /// Plays every move of every position once, spread over all cores.
+    fn forward_pass(&mut self) {
+        let len = table_len(self.attackers, self.defenders);
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let chunk = len.div_ceil(threads).max(1);
+        let this = &*self;
+        let results: Vec<(Vec<u16>, Vec<Node>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..len)
+                .step_by(chunk)
+                .map(|start| scope.spawn(move || this.forward_chunk(start..(start + chunk).min(len))))
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        for (values, nodes) in results {
+            self.values.extend(values);
+            self.nodes.extend(nodes);
+        }
+    }
+
+    fn forward_chunk(&self, indices: std::ops::Range<usize>) -> (Vec<u16>, Vec<Node>) {
+        let mut values = Vec::with_capacity(indices.len());
+        let mut nodes = Vec::with_capacity(indices.len());
+        for index in indices {
+            let placement = Placement::from_index(self.attackers, self.defenders, index);
+            if !placement.is_valid() {
+                values.push(INVALID);
+                nodes.push(Node::default());
+                continue;
+            }
+            values.push(UNKNOWN);
+
+            let position = placement.to_game(&self.empty);
+            let mut node = Node::default();
+            for mv in position.legal_moves() {
+                let mut child = position.clone();
+                if child.apply_move(mv).is_err() {
+                    continue;
+                }
+                let result = match child.winner() {
+                    Some(winner) if winner == placement.side => TablebaseResult::Win(1),
+                    Some(_) => TablebaseResult::Loss(1),
+                    None if !placement.is_capture(&child) => {
+                        node.remaining += 1; // Solved in this table
+                        continue;
+                    }
+                    None => self
+                        .smaller
+                        .probe(&child)
+                        .expect("smaller tables are generated first")
+                        .for_mover(),
+                };
+                match result {
+                    TablebaseResult::Win(distance) => {
+                        let distance = distance as u16;
+                        if node.best_win == 0 || distance < node.best_win {
+                            node.best_win = distance;
+                        }
+                    }
+                    TablebaseResult::Loss(distance) => node.longest_loss = node.longest_loss.max(distance as u16),
+                    TablebaseResult::Draw => node.has_draw = true,
+                }
+            }
+            nodes.push(node);
+        }
+        (values, nodes)
+    }
+
+    fn push(&mut self, index: usize, value: u16) {
+        let distance = (value & DISTANCE_MASK) as usize;
+        if self.buckets.len() <= distance {
+            self.buckets.resize_with(distance + 1, Vec::new);
+        }
+        self.buckets[distance].push((index as u32, value));
+    }
+
+    /// Every move of a position without a win is resolved: it draws if a
+    /// move draws, otherwise it loses as late as possible.
+    fn settle_without_win(&mut self, index: usize) {
+        let node = self.nodes[index];
+        if node.has_draw {
+            self.values[index] = DRAW;
+        } else {
+            self.push(index, LOSS | node.longest_loss);
+        }
+    }
+
+    /// Passes the newly settled result of `index` on to the positions of this
+    /// table that can move into it.
+    fn update_predecessors(&mut self, index: usize, value: u16) {
+        let distance = value & DISTANCE_MASK;
+        let lost = value & LOSS != 0;
+        let placement = Placement::from_index(self.attackers, self.defenders, index);
+
+        for predecessor in self.predecessors(&placement, index) {
+            if self.values[predecessor] != UNKNOWN {
+                continue;
+            }
+            if lost {
+                self.push(predecessor, WIN | (distance + 1));
+                continue;
+            }
+            let node = &mut self.nodes[predecessor];
+            node.remaining -= 1;
+            node.longest_loss = node.longest_loss.max(distance + 1);
+            if node.remaining == 0 && node.best_win == 0 {
+                self.settle_without_win(predecessor);
+            }
+        }
+    }
+
+    /// Unsettled positions of this table with a move leading to `placement`:
+    /// the side that just moved slides one of its pieces back. Each candidate
+    /// is checked by playing the move forward, so the rules engine decides
+    /// what is a legal, non-capturing move that does not end the game.
+    fn predecessors(&self, placement: &Placement, index: usize) -> Vec<usize> {
+        let mover = opponent(placement.side);
+        let mut pieces: Vec<(usize, CellType)> = Vec::new();
+        if mover == CellType::Attacker {
+            pieces.extend(placement.attackers.iter().map(|&square| (square, CellType::Attacker)));
+        } else {
+            pieces.push((placement.king, CellType::King));
+            pieces.extend(placement.defenders.iter().map(|&square| (square, CellType::Defender)));
+        }
+
+        let mut predecessors = Vec::new();
+        for (to, piece) in pieces {
+            for (d_row, d_col) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (mut row, mut col) = ((to / SIZE) as isize, (to % SIZE) as isize);
+                loop {
+                    row += d_row;
+                    col += d_col;
+                    if row < 0 || col < 0 || row >= SIZE as isize || col >= SIZE as isize {
+                        break;
+                    }
+                    let from = row as usize * SIZE + col as usize;
+                    if !placement.is_empty(from) {
+                        break;
+                    }
+
+                    let mut previous = placement.clone();
+                    previous.side = mover;
+                    match piece {
+                        CellType::King => previous.king = from,
+                        CellType::Attacker => replace_square(&mut previous.attackers, to, from),
+                        _ => replace_square(&mut previous.defenders, to, from),
+                    }
+                    if !previous.is_valid() {
+                        continue;
+                    }
+                    let previous_index = previous.index();
+                    if self.values[previous_index] != UNKNOWN {
+                        continue;
+                    }
+
+                    let mut game = previous.to_game(&self.empty);
+                    let mv = Move {
+                        from: (from / SIZE, from % SIZE),
+                        to: (to / SIZE, to % SIZE),
+                    };
+                    if !game.legal_moves().contains(&mv) || game.apply_move(mv).is_err() || game.winner().is_some() {
+                        continue;
+                    }
+                    if !placement.is_capture(&game) && Placement::of(&game).is_some_and(|reached| reached.index() == index) {
+                        predecessors.push(previous_index);
+                    }
+                }
+            }
+        }
+        predecessors
+    }
+}
+
+fn replace_square(squares: &mut [usize], old: usize, new: usize) {
+    if let Some(square) = squares.iter_mut().find(|square| **square == old) {
+        *square = new;
+    }
+    squares.sort_unstable();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(side: CellType, king: usize, attackers: &[usize], defenders: &[usize]) -> Placement {
+        Placement {
+            side,
+            king,
+            attackers: attackers.to_vec(),
+            defenders: defenders.to_vec(),
+        }
+    }
+
+    fn square(row: usize, col: usize) -> usize {
+        row * SIZE + col
+    }
+
+    #[test]
+    fn indices_and_placements_correspond() {
+        for (attackers, defenders) in [(0, 0), (1, 0), (0, 1), (2, 0), (1, 1), (0, 2)] {
+            for index in 0..table_len(attackers, defenders) {
+                let placement = Placement::from_index(attackers, defenders, index);
+                assert_eq!(placement.material(), (attackers, defenders));
+                let mut squares: Vec<usize> = placement.attackers.iter().chain(&placement.defenders).copied().collect();
+                squares.push(placement.king);
+                squares.sort_unstable();
+                squares.dedup();
+                assert_eq!(squares.len(), attackers + defenders + 1, "{:?}", placement);
+                assert!(placement.attackers.is_sorted() && placement.defenders.is_sorted());
+                assert_eq!(placement.index(), index, "{:?}", placement);
+            }
+        }
+    }
+
+    #[test]
+    fn every_valid_placement_round_trips() {
+        for side in [CellType::Attacker, CellType::Defender] {
+            for king in 0..SQUARES {
+                for attacker in (0..SQUARES).filter(|&square| square != king) {
+                    for defender in (0..SQUARES).filter(|&square| square != king && square != attacker) {
+                        let placement = placement(side, king, &[attacker], &[defender]);
+                        if placement.is_valid() {
+                            assert_eq!(Placement::from_index(1, 1, placement.index()), placement);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_results() {
+        let tablebase = Tablebase::generate(2).unwrap();
+        let empty = GameState::empty_board();
+
+        // The king on an edge with open lines to both corners escapes at once
+        let escape = placement(CellType::Defender, square(0, 3), &[], &[]).to_game(&empty);
+        assert_eq!(tablebase.probe(&escape), Some(TablebaseResult::Win(1)));
+        let (mv, result) = tablebase.best_move(&escape).unwrap();
+        assert!([(0, 0), (0, SIZE - 1)].contains(&mv.to));
+        assert_eq!(result, TablebaseResult::Win(1));
+
+        // A single attacker can close only one of the two lines
+        let too_late = placement(CellType::Attacker, square(0, 3), &[square(5, 5)], &[]).to_game(&empty);
+        assert_eq!(tablebase.probe(&too_late), Some(TablebaseResult::Loss(2)));
+
+        // Positions with more pieces than the tablebase are not in it
+        let larger = placement(CellType::Attacker, square(0, 3), &[square(5, 5), square(5, 1)], &[]).to_game(&empty);
+        assert_eq!(tablebase.probe(&larger), None);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let tablebase = Tablebase::generate(2).unwrap();
+        let path = std::env::temp_dir().join(format!("hnefatafl_tablebase_test_{}.btb", std::process::id()));
+        tablebase.save(&path).unwrap();
+        let loaded = Tablebase::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.max_pieces(), tablebase.max_pieces());
+        assert_eq!(loaded.tables, tablebase.tables);
+        assert_eq!(loaded.tables(), tablebase.tables());
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BTB");
+        bytes.extend_from_slice(&[FORMAT_VERSION + 1, 2]);
+        assert!(Tablebase::read(&mut bytes.as_slice()).err().unwrap().contains("format version"));
+        assert!(Tablebase::read(&mut &b"XYZ\x01\x02"[..]).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Tactical patterns around the king and captures, for engines, the
+//! evaluation, hints and board overlays.
+//!
+//! The king wins by reaching a corner, so its escape routes are straight
+//! lines to a corner, taken in one move or after one more king move. A king
+//! that threatens to escape along two lines at once usually cannot be
+//! stopped: `is_forced_escape` checks every attacker reply to find out.
+//!
+//! Threat detection looks at the moves each side could make next, whoever is
+//! to move, so it also answers "what would the opponent do if I passed".
+
+use crate::cell::CellType;
+use crate::variant::{Move, Variant};
+
+/// A way for the king to reach `corner`: `king_move` lands on the corner, or
+/// on a square with an open line to it, from which a second move escapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EscapeRoute {
+    pub king_move: Move,
+    pub corner: (usize, usize),
+}
+
+impl EscapeRoute {
+    /// Whether the route escapes in a single move.
+    pub fn is_immediate(&self) -> bool {
+        self.king_move.to == self.corner
+    }
+}
+
+/// A piece the opponent can capture on their next move, and the moves that do it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureThreat {
+    pub square: (usize, usize),
+    pub moves: Vec<Move>,
+}
+
+/// The four corner squares of `G`'s board.
+pub fn corners<G: Variant>() -> [(usize, usize); 4] {
+    let last = G::SIZE - 1;
+    [(0, 0), (0, last), (last, 0), (last, last)]
+}
+
+/// Every escape route of the king, one per king move and corner; empty once
+/// the king is captured.
+pub fn escape_routes<G: Variant>(position: &G) -> Vec<EscapeRoute> {
+    let Some(king) = position.king_position() else {
+        return Vec::new();
+    };
+    let corners = corners::<G>();
+    let mut routes = Vec::new();
+    for to in position.valid_moves_from(king) {
+        let king_move = Move { from: king, to };
+        if corners.contains(&to) {
+            routes.push(EscapeRoute { king_move, corner: to });
+            continue;
+        }
+        for &corner in &corners {
+            if clear_line(position, to, corner, king) {
+                routes.push(EscapeRoute { king_move, corner });
+            }
+        }
+    }
+    routes
+}
+
+/// King moves that reach a corner right away, whoever is to move.
+pub fn escape_moves<G: Variant>(position: &G) -> Vec<Move> {
+    let Some(king) = position.king_position() else {
+        return Vec::new();
+    };
+    let corners = corners::<G>();
+    position
+        .valid_moves_from(king)
+        .into_iter()
+        .filter(|to| corners.contains(to))
+        .map(|to| Move { from: king, to })
+        .collect()
+}
+
+/// Whether the king escapes whatever the attackers do: the defenders are to
+/// move with an escape move, or the attackers are to move and none of their
+/// moves both keeps the game going and closes every escape move.
+pub fn is_forced_escape<G: Variant>(position: &G) -> bool {
+    if position.outcome().is_some() || escape_moves(position).is_empty() {
+        return false;
+    }
+    if position.side_to_move() != CellType::Attacker {
+        return true;
+    }
+    position.legal_moves().into_iter().all(|mv| {
+        let mut child = position.clone();
+        child.apply_move(mv).is_ok() && child.outcome().is_none() && !escape_moves(&child).is_empty()
+    })
+}
+
+/// Defender king moves that set up a forced escape, such as a double threat
+/// along two open lines; empty unless the defenders are to move.
+pub fn forcing_king_moves<G: Variant>(position: &G) -> Vec<Move> {
+    if position.outcome().is_some() || position.side_to_move() == CellType::Attacker {
+        return Vec::new();
+    }
+    let Some(king) = position.king_position() else {
+        return Vec::new();
+    };
+    let corners = corners::<G>();
+    position
+        .valid_moves_from(king)
+        .into_iter()
+        .filter(|to| !corners.contains(to))
+        .map(|to| Move { from: king, to })
+        .filter(|&mv| {
+            let mut child = position.clone();
+            child.apply_move(mv).is_ok() && is_forced_escape(&child)
+        })
+        .collect()
+}
+
+/// Pieces of `side` (the king counts as a defender, but is never captured
+/// this way) that the other side could capture with its next move.
+pub fn threatened_pieces<G: Variant>(position: &G, side: CellType) -> Vec<CaptureThreat> {
+    let mut threats: Vec<CaptureThreat> = Vec::new();
+    for row in 0..G::SIZE {
+        for col in 0..G::SIZE {
+            let piece = position.cell((row, col)).cell_type;
+            if piece == CellType::Empty || G::belongs_to(piece, side) {
+                continue;
+            }
+            for to in position.valid_moves_from((row, col)) {
+                let mv = Move { from: (row, col), to };
+                for square in position.captured_by(mv) {
+                    match threats.iter_mut().find(|threat| threat.square == square) {
+                        Some(threat) => threat.moves.push(mv),
+                        None => threats.push(CaptureThreat { square, moves: vec![mv] }),
+                    }
+                }
+            }
+        }
+    }
+    threats.sort_by_key(|threat| threat.square);
+    threats
+}
+
+/// Attacker moves that would capture the king and win at once, whoever is to move.
+pub fn king_capture_threats<G: Variant>(position: &G) -> Vec<Move> {
+    if position.outcome().is_some() {
+        return Vec::new();
+    }
+    let mut attacking = position.clone();
+    attacking.set_side_to_move(CellType::Attacker);
+    attacking
+        .legal_moves()
+        .into_iter()
+        .filter(|&mv| {
+            let mut child = attacking.clone();
+            child.apply_move(mv).is_ok() && child.winner() == Some(CellType::Attacker)
+        })
+        .collect()
+}
+
+/// Whether the king, standing on `from` after leaving `king`, could move
+/// straight on to `to`: same row or column and nothing in between.
+fn clear_line<G: Variant>(position: &G, from: (usize, usize), to: (usize, usize), king: (usize, usize)) -> bool {
+    if from.0 != to.0 && from.1 != to.1 {
+        return false;
+    }
+    let (d_row, d_col) = ((to.0 as isize - from.0 as isize).signum(), (to.1 as isize - from.1 as isize).signum());
+    let mut pos = from;
+    while pos != to {
+        pos = ((pos.0 as isize + d_row) as usize, (pos.1 as isize + d_col) as usize);
+        if pos != king && position.cell(pos).cell_type != CellType::Empty {
+            return false;
+        }
+    }
+    true
+}
@@ -0,0 +1,17 @@
+//! Helpers shared by the unit tests.
+
+use crate::agent::{Agent, Clock, RandomAgent};
+use crate::variant::{Move, Variant};
+
+/// The moves of a game played at random from the start, for up to `plies` moves.
+pub fn random_moves<G: Variant>(plies: usize, seed: u64) -> Vec<Move> {
+    let mut agent = RandomAgent::new(seed);
+    let mut game = G::new_game();
+    let mut moves = Vec::new();
+    while moves.len() < plies && game.outcome().is_none() && game.has_legal_move() {
+        let mv = agent.choose_move(&game, &Clock::unlimited());
+        game.play(mv).unwrap();
+        moves.push(mv);
+    }
+    moves
+}
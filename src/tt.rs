@@ -0,0 +1,220 @@
+//! Transposition table: a fixed-size cache of search results indexed by a
+//! Zobrist hash of the position.
+//!
+//! Entries live in buckets of two slots. The first slot keeps the deepest
+//! result (unless it is from an older search), the second always takes the
+//! latest one, so deep results survive while recent ones are still found.
+//!
+//! The table is lock-free, so that the threads of a parallel search can share
+//! one: each slot is read and written as two independent atomics, and a slot
+//! whose halves come from different writes is detected and ignored.
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::cell::CellType;
+use crate::rng;
+use crate::variant::{Move, Variant};
+
+/// Default size of a search engine's table.
+pub const DEFAULT_TABLE_MB: usize = 16;
+
+const ZOBRIST_SEED: u64 = 0x7a0b_2157_4a1f_e3c5;
+
+/// Zobrist hash of the pieces on the board and the side to move.
+/// Move counters are not included, so positions that differ only in how
+/// close they are to the move limit share a hash.
+pub fn position_hash<G: Variant>(position: &G) -> u64 {
+    let mut hash = 0;
+    for row in 0..G::SIZE {
+        for col in 0..G::SIZE {
+            let piece = match position.cell((row, col)).cell_type {
+                CellType::Empty => continue,
+                CellType::Attacker => 0,
+                CellType::Defender => 1,
+                CellType::King => 2,
+            };
+            hash ^= rng::derive_seed(ZOBRIST_SEED, (piece * 256 + row * G::SIZE + col) as u64);
+        }
+    }
+    if position.side_to_move() == CellType::Defender {
+        hash ^= rng::derive_seed(ZOBRIST_SEED, 3 * 256);
+    }
+    hash
+}
+
+/// How a stored score relates to the true score of the position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    Lower, // The true score is at least this (the search failed high)
+    Upper, // The true score is at most this (the search failed low)
+}
+
+/// Result of searching a position, as stored in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtEntry {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u32,
+    pub bound: Bound,
+}
+
+/// The packed entry (`pack_entry`) and the hash XORed with it, so a slot
+/// torn by two threads storing at once no longer matches its hash.
+#[derive(Debug, Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> (u64, u64) {
+        let data = self.data.load(Ordering::Relaxed);
+        (self.key.load(Ordering::Relaxed) ^ data, data)
+    }
+
+    fn store(&self, hash: u64, data: u64) {
+        self.key.store(hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+}
+
+const NO_MOVE: u16 = u16::MAX; // (15, 15) -> (15, 15) is never a move
+
+/// Fixed-size table that any number of search threads can share: every
+/// method takes `&self`, and no locks are taken.
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    bucket_mask: usize,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// A table using at most `megabytes` of memory (at least one bucket).
+    pub fn new(megabytes: usize) -> Self {
+        let bytes = megabytes * 1024 * 1024;
+        let buckets = (bytes / (2 * std::mem::size_of::<Slot>())).max(1);
+        // Round down to a power of two so a bucket is found by masking the hash
+        let buckets = 1 << (usize::BITS - 1 - buckets.leading_zeros());
+        TranspositionTable {
+            slots: (0..buckets * 2).map(|_| Slot::default()).collect(),
+            bucket_mask: buckets - 1,
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.slots.iter().for_each(|slot| slot.store(0, 0));
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Marks the start of a new search, so older entries are replaced first.
+    pub fn new_search(&self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        self.generation.store((generation + 1) & 0x3f, Ordering::Relaxed);
+    }
+
+    /// Number of entries the table can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Per mille of the first 1000 slots in use by the current search.
+    pub fn hashfull(&self) -> u32 {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = self.slots.len().min(1000);
+        let used = self.slots[..sample]
+            .iter()
+            .filter(|slot| {
+                let flags = flags(slot.load().1);
+                flags & 0b11 != 0 && flags >> 2 == generation
+            })
+            .count();
+        (used * 1000 / sample) as u32
+    }
+
+    pub fn probe(&self, hash: u64) -> Option<TtEntry> {
+        let bucket = self.bucket(hash);
+        self.slots[bucket..bucket + 2]
+            .iter()
+            .map(Slot::load)
+            .find(|&(key, data)| key == hash && flags(data) & 0b11 != 0)
+            .map(|(_, data)| unpack_entry(data))
+    }
+
+    pub fn store(&self, hash: u64, entry: TtEntry) {
+        let bucket = self.bucket(hash);
+        let generation = self.generation.load(Ordering::Relaxed);
+        let (deep_key, deep) = self.slots[bucket].load();
+        let index = if flags(deep) & 0b11 == 0
+            || deep_key == hash
+            || flags(deep) >> 2 != generation
+            || entry.depth >= depth(deep)
+        {
+            bucket
+        } else {
+            bucket + 1
+        };
+
+        // Keep the known best move when a new result for the same position has none
+        let (previous_key, previous) = self.slots[index].load();
+        let best_move = match entry.best_move {
+            Some(mv) => pack_move(mv),
+            None if previous_key == hash => (previous >> 32) as u16,
+            None => NO_MOVE,
+        };
+        let bound = match entry.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let data = pack_entry(entry.score, best_move, entry.depth.min(u8::MAX as u32) as u8, bound | generation << 2);
+        self.slots[index].store(hash, data);
+    }
+
+    fn bucket(&self, hash: u64) -> usize {
+        (hash as usize & self.bucket_mask) * 2
+    }
+}
+
+/// Bits 0-31: score, 32-47: best move (4 bits per coordinate: from row, from
+/// col, to row, to col; `NO_MOVE` if none), 48-55: depth, 56-57: bound
+/// (0 = empty slot), 58-63: search generation.
+fn pack_entry(score: i32, best_move: u16, depth: u8, flags: u8) -> u64 {
+    score as u32 as u64 | (best_move as u64) << 32 | (depth as u64) << 48 | (flags as u64) << 56
+}
+
+fn depth(data: u64) -> u32 {
+    (data >> 48) as u8 as u32
+}
+
+fn flags(data: u64) -> u8 {
+    (data >> 56) as u8
+}
+
+fn unpack_entry(data: u64) -> TtEntry {
+    let packed = (data >> 32) as u16;
+    let best_move = (packed != NO_MOVE).then_some({
+        let packed = packed as usize;
+        Move {
+            from: (packed >> 12, (packed >> 8) & 0xf),
+            to: ((packed >> 4) & 0xf, packed & 0xf),
+        }
+    });
+    let bound = match flags(data) & 0b11 {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        _ => Bound::Upper,
+    };
+    TtEntry {
+        best_move,
+        score: data as u32 as i32,
+        depth: depth(data),
+        bound,
+    }
+}
+
+/// Boards are at most 11x11, so every coordinate fits in 4 bits.
+fn pack_move(mv: Move) -> u16 {
+    (mv.from.0 << 12 | mv.from.1 << 8 | mv.to.0 << 4 | mv.to.1) as u16
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cell::{Cell, CellType};
+use crate::symmetry::{self, Symmetry};
+
+/// Total number of moves (both sides) after which a game is declared a draw.
+pub const MOVE_LIMIT: u32 = 100;
+
+/// The other side: `CellType::Attacker` for the defenders and vice versa.
+pub fn opponent(side: CellType) -> CellType {
+    if side == CellType::Attacker { CellType::Defender } else { CellType::Attacker }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+}
+
+/// Common interface over the rule variants (Brandubh, Copenhagen).
+///
+/// Each variant keeps its own `GameState`; this trait exposes just enough of it
+/// for code that should work on any board size (move generation, playouts, ...).
+pub trait Variant: Clone + Send + Sync + 'static {
+    const NAME: &'static str;
+    const SIZE: usize;
+    /// Whether the throne helps capture pieces next to it (Copenhagen) or not (Brandubh).
+    const HOSTILE_THRONE: bool;
+
+    /// Starting position of the variant.
+    fn new_game() -> Self;
+
+    fn cell(&self, pos: (usize, usize)) -> Cell;
+
+    /// Overwrites a square, e.g. to set up or transform a position.
+    fn set_cell(&mut self, pos: (usize, usize), cell: Cell);
+
+    /// Side to move, either `CellType::Attacker` or `CellType::Defender`.
+    fn side_to_move(&self) -> CellType;
+
+    fn set_side_to_move(&mut self, side: CellType);
+
+    /// Destinations reachable by the piece on `pos`.
+    fn valid_moves_from(&self, pos: (usize, usize)) -> Vec<(usize, usize)>;
+
+    /// Plays a move without checking it and updates the move counters.
+    /// Callers are expected to pass a move from `legal_moves`.
+    fn apply_move(&mut self, mv: Move) -> Result<(), String>;
+
+    /// Winner decided by the rules engine (king escape, capture, no moves left).
+    fn winner(&self) -> Option<CellType>;
+
+    fn set_winner(&mut self, winner: Option<CellType>);
+
+    /// Moves played so far by both sides.
+    fn move_count(&self) -> u32;
+
+    /// Moves played so far by each side, as (attacker, defender).
+    fn side_move_counts(&self) -> (u32, u32);
+
+    fn set_side_move_counts(&mut self, attacker_moves: u32, defender_moves: u32);
+
+    /// Whether a piece of type `piece` belongs to `side`.
+    fn belongs_to(piece: CellType, side: CellType) -> bool {
+        match side {
+            CellType::Attacker => piece == CellType::Attacker,
+            CellType::Defender => piece == CellType::Defender || piece == CellType::King,
+            _ => false,
+        }
+    }
+
+    /// All legal moves for the side to move.
+    fn legal_moves(&self) -> Vec<Move> {
+        let side = self.side_to_move();
+        let mut moves = Vec::new();
+        for row in 0..Self::SIZE {
+            for col in 0..Self::SIZE {
+                if Self::belongs_to(self.cell((row, col)).cell_type, side) {
+                    for to in self.valid_moves_from((row, col)) {
+                        moves.push(Move { from: (row, col), to });
+                    }
+                }
+            }
+        }
+        moves
+    }
+
+    /// Whether the side to move has any legal move, without listing them all.
+    fn has_legal_move(&self) -> bool {
+        let side = self.side_to_move();
+        (0..Self::SIZE).any(|row| {
+            (0..Self::SIZE).any(|col| {
+                Self::belongs_to(self.cell((row, col)).cell_type, side)
+                    && !self.valid_moves_from((row, col)).is_empty()
+            })
+        })
+    }
+
+    /// The starting position with every piece removed; corners and throne keep their flags.
+    fn empty_board() -> Self {
+        let mut game = Self::new_game();
+        for row in 0..Self::SIZE {
+            for col in 0..Self::SIZE {
+                let cell = game.cell((row, col));
+                game.set_cell(
+                    (row, col),
+                    Cell {
+                        cell_type: CellType::Empty,
+                        is_corner: cell.is_corner,
+                        is_throne: cell.is_throne,
+                        is_selected: false,
+                        is_possible_move: false,
+                    },
+                );
+            }
+        }
+        game
+    }
+
+    /// Builds a position from a board as sent by the server, keyed by `"(row, col)"`.
+    fn from_board_map(board: &HashMap<String, CellType>, side_to_move: CellType) -> Result<Self, String> {
+        let mut game = Self::empty_board();
+        for (key, &cell_type) in board {
+            let coords: Vec<usize> = key
+                .trim_start_matches('(')
+                .trim_end_matches(')')
+                .split(',')
+                .map(|part| part.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("Invalid square key: {}", key))?;
+            if coords.len() != 2 || coords[0] >= Self::SIZE || coords[1] >= Self::SIZE {
+                return Err(format!("Invalid square key: {}", key));
+            }
+            let pos = (coords[0], coords[1]);
+            let cell = game.cell(pos);
+            game.set_cell(pos, Cell { cell_type, ..cell });
+        }
+        game.set_side_to_move(side_to_move);
+        Ok(game)
+    }
+
+    /// Squares whose pieces `mv` would capture, following the custodial capture rule
+    /// of `check_captures`: the moved piece and a friendly piece (or a hostile
+    /// square) on both sides of an enemy piece. The king itself is never captured this way.
+    fn captured_by(&self, mv: Move) -> Vec<(usize, usize)> {
+        let mover = self.cell(mv.from).cell_type;
+        let victim = if mover == CellType::Attacker { CellType::Defender } else { CellType::Attacker };
+        let mut captured = Vec::new();
+        for (d_row, d_col) in [(-1isize, 0isize), (1, 0), (0, -1), (0, 1)] {
+            let neighbor = (mv.to.0 as isize + d_row, mv.to.1 as isize + d_col);
+            let beyond = (mv.to.0 as isize + 2 * d_row, mv.to.1 as isize + 2 * d_col);
+            let size = Self::SIZE as isize;
+            if beyond.0 < 0 || beyond.1 < 0 || beyond.0 >= size || beyond.1 >= size {
+                continue;
+            }
+            let neighbor = (neighbor.0 as usize, neighbor.1 as usize);
+            if self.cell(neighbor).cell_type != victim {
+                continue;
+            }
+            let anvil = self.cell((beyond.0 as usize, beyond.1 as usize));
+            let hostile = anvil.is_corner
+                || if mover == CellType::Attacker {
+                    anvil.cell_type == CellType::Attacker
+                        || (Self::HOSTILE_THRONE && anvil.is_throne && anvil.cell_type == CellType::Empty)
+                } else {
+                    anvil.cell_type == CellType::Defender
+                        || anvil.cell_type == CellType::King
+                        || (Self::HOSTILE_THRONE && anvil.is_throne)
+                };
+            if hostile {
+                captured.push(neighbor);
+            }
+        }
+        captured
+    }
+
+    fn is_capture(&self, mv: Move) -> bool {
+        !self.captured_by(mv).is_empty()
+    }
+
+    /// Validates `mv` against the side to move and the legal destinations before playing it.
+    fn play(&mut self, mv: Move) -> Result<(), String> {
+        if self.outcome().is_some() {
+            return Err("Game is already over.".to_string());
+        }
+        if mv.from.0 >= Self::SIZE || mv.from.1 >= Self::SIZE {
+            return Err("Invalid cell coordinates.".to_string());
+        }
+        if !Self::belongs_to(self.cell(mv.from).cell_type, self.side_to_move()) {
+            return Err("Cannot move opponents piece.".to_string());
+        }
+        if !self.valid_moves_from(mv.from).contains(&mv.to) {
+            return Err("Illegal move.".to_string());
+        }
+        self.apply_move(mv)
+    }
+
+    /// Final result of the game: the winner, `CellType::Empty` for a draw on
+    /// reaching `MOVE_LIMIT`, or `None` while the game is still running.
+    fn outcome(&self) -> Option<CellType> {
+        self.winner()
+            .or_else(|| (self.move_count() >= MOVE_LIMIT).then_some(CellType::Empty))
+    }
+
+    /// Number of pieces of the given type on the board.
+    fn count_pieces(&self, piece: CellType) -> usize {
+        let mut count = 0;
+        for row in 0..Self::SIZE {
+            for col in 0..Self::SIZE {
+                if self.cell((row, col)).cell_type == piece {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Square of the king, or `None` once it has been captured.
+    fn king_position(&self) -> Option<(usize, usize)> {
+        for row in 0..Self::SIZE {
+            for col in 0..Self::SIZE {
+                if self.cell((row, col)).cell_type == CellType::King {
+                    return Some((row, col));
+                }
+            }
+        }
+        None
+    }
+
+    /// This position with `symmetry` applied to every square; side to move and counters are kept.
+    fn transformed(&self, symmetry: Symmetry) -> Self {
+        let mut transformed = self.clone();
+        for row in 0..Self::SIZE {
+            for col in 0..Self::SIZE {
+                transformed.set_cell(symmetry.apply((row, col), Self::SIZE), self.cell((row, col)));
+            }
+        }
+        transformed
+    }
+
+    /// Compact key identifying the position: the piece on every square plus the side to move.
+    fn position_key(&self) -> Vec<u8> {
+        symmetry::transformed_key(self, Symmetry::Identity)
+    }
+
+    /// The symmetry mapping this position to its canonical form, the transform
+    /// with the smallest `position_key`. Symmetric positions share the same
+    /// canonical form.
+    fn canonical_symmetry(&self) -> Symmetry {
+        Symmetry::ALL
+            .into_iter()
+            .min_by_key(|&symmetry| symmetry::transformed_key(self, symmetry))
+            .unwrap()
+    }
+
+    /// Canonical form of this position and the symmetry that produced it. Moves
+    /// found in the canonical position map back with `symmetry.inverse().apply_move(..)`.
+    fn canonical_form(&self) -> (Self, Symmetry) {
+        let symmetry = self.canonical_symmetry();
+        (self.transformed(symmetry), symmetry)
+    }
+
+    /// The symmetries that map this position onto itself: only `Identity`,
+    /// unless it is its own mirror image or rotation (like the start position).
+    fn self_symmetries(&self) -> Vec<Symmetry> {
+        let key = self.position_key();
+        Symmetry::ALL
+            .into_iter()
+            .filter(|&symmetry| symmetry::transformed_key(self, symmetry) == key)
+            .collect()
+    }
+
+    /// `mv` as played in the canonical form of this position. In a position that
+    /// is its own mirror image several moves are equivalent, and the smallest of
+    /// their images under `self_symmetries` of the canonical form is taken, so
+    /// that they all get the same canonical move.
+    fn canonical_move(&self, mv: Move) -> Move {
+        let (canonical, symmetry) = self.canonical_form();
+        let mv = symmetry.apply_move(mv, Self::SIZE);
+        canonical
+            .self_symmetries()
+            .into_iter()
+            .map(|self_symmetry| self_symmetry.apply_move(mv, Self::SIZE))
+            .min_by_key(|mv| (mv.from, mv.to))
+            .unwrap()
+    }
+}